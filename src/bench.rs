@@ -0,0 +1,406 @@
+//! `rm-pad bench`: measure SSH round trips, the tablet-to-host latency of
+//! the forwarding pipeline, throughput and device report rates.
+//!
+//! Latency is measured from an event frame's tablet timestamp to the moment
+//! the pipeline has written it to the virtual device and asks for the next
+//! event. The tablet and host clocks are aligned beforehand with an
+//! NTP-style handshake over SSH: the reply with the shortest round trip
+//! gives the offset, assuming the delay is symmetric.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use evdevil::event::{EventType, Syn};
+use serde::Serialize;
+
+use rm_pad::config::Config;
+use rm_pad::device::DeviceProfile;
+use rm_pad::input::{self, parse_event_time, parse_input_event, SshSource, VirtualDevice};
+use rm_pad::palm;
+use rm_pad::ssh;
+use rm_pad::state::{InputKind, RuntimeState, SharedState};
+
+/// Round trips used to estimate the clock offset and SSH latency.
+const HANDSHAKE_SAMPLES: usize = 20;
+
+/// Upper bounds (ms) of the latency histogram buckets; the last is open.
+const HISTOGRAM_BUCKETS_MS: [u64; 8] = [1, 2, 5, 10, 20, 50, 100, 200];
+
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Time for events queued while the virtual devices were created to drain.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Distribution of a set of samples, in microseconds.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Summary {
+    pub samples: usize,
+    pub min: i64,
+    pub mean: i64,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub max: i64,
+}
+
+impl Summary {
+    fn new(samples: &[i64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_unstable();
+        Some(Self {
+            samples: sorted.len(),
+            min: sorted[0],
+            mean: sorted.iter().sum::<i64>() / sorted.len() as i64,
+            p50: percentile(&sorted, 50.0),
+            p90: percentile(&sorted, 90.0),
+            p99: percentile(&sorted, 99.0),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// Nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[i64], p: f64) -> i64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Sample counts per `HISTOGRAM_BUCKETS_MS` bucket, plus one for the rest.
+fn histogram(samples_us: &[i64]) -> Vec<usize> {
+    let mut counts = vec![0; HISTOGRAM_BUCKETS_MS.len() + 1];
+    for &sample in samples_us {
+        let bucket = HISTOGRAM_BUCKETS_MS
+            .iter()
+            .position(|&max_ms| sample < (max_ms * 1000) as i64)
+            .unwrap_or(HISTOGRAM_BUCKETS_MS.len());
+        counts[bucket] += 1;
+    }
+    counts
+}
+
+fn bucket_label(bucket: usize) -> String {
+    match bucket {
+        0 => format!("< {} ms", HISTOGRAM_BUCKETS_MS[0]),
+        b if b == HISTOGRAM_BUCKETS_MS.len() => format!(">= {} ms", HISTOGRAM_BUCKETS_MS[b - 1]),
+        b => format!("{}-{} ms", HISTOGRAM_BUCKETS_MS[b - 1], HISTOGRAM_BUCKETS_MS[b]),
+    }
+}
+
+/// One clock handshake sample: host send time, tablet time, host receive
+/// time, all in microseconds since the Unix epoch.
+type ClockSample = (i64, i64, i64);
+
+/// Tablet clock minus host clock and the round trip, both in microseconds,
+/// from the sample with the shortest round trip.
+fn clock_offset(samples: &[ClockSample]) -> Option<(i64, i64)> {
+    let &(sent, tablet, received) = samples.iter().min_by_key(|(sent, _, received)| received - sent)?;
+    Some((tablet - (sent + received) / 2, received - sent))
+}
+
+fn now_us() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as i64
+}
+
+/// Ask the tablet for its time repeatedly over one SSH channel.
+fn clock_handshake(config: &Config) -> Result<Vec<ClockSample>, Box<dyn std::error::Error + Send + Sync>> {
+    let session = ssh::connect_for_detection(config)?;
+    let mut channel = session.channel_session()?;
+    channel.exec("while read -r _; do date +%s%N; done")?;
+
+    let mut reader = BufReader::new(channel.stream(0));
+    let mut samples = Vec::with_capacity(HANDSHAKE_SAMPLES);
+    let mut line = String::new();
+
+    for _ in 0..HANDSHAKE_SAMPLES {
+        let sent = now_us();
+        channel.write_all(b"\n")?;
+        channel.flush()?;
+        line.clear();
+        reader.read_line(&mut line)?;
+        let received = now_us();
+
+        let nanos: i64 = line
+            .trim()
+            .parse()
+            .ok()
+            .filter(|_| line.trim().len() >= 19)
+            .ok_or_else(|| format!("Unexpected reply from the tablet's date: '{}'", line.trim()))?;
+        samples.push((sent, nanos / 1000, received));
+    }
+
+    let _ = channel.send_eof();
+    Ok(samples)
+}
+
+#[derive(Default)]
+struct InputStats {
+    events: u64,
+    /// Tablet timestamps (µs) of each frame's `SYN_REPORT`.
+    frame_times: Vec<i64>,
+    /// Tablet timestamp to written, per frame (µs).
+    latencies: Vec<i64>,
+}
+
+/// Wraps a tablet stream to time each frame through the pipeline reading it.
+struct Probe<R> {
+    inner: R,
+    event_size: usize,
+    /// Tablet clock minus host clock (µs).
+    offset: i64,
+    stats: Arc<Mutex<InputStats>>,
+    partial: Vec<u8>,
+    /// Host-clock tablet timestamp of the frame the pipeline is processing.
+    frame_end: Option<i64>,
+}
+
+impl<R: Read> Read for Probe<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Asking for more input means the previous frame was written out
+        if let Some(frame_end) = self.frame_end.take() {
+            self.stats.lock().unwrap().latencies.push(now_us() - frame_end);
+        }
+
+        let n = self.inner.read(buf)?;
+        self.partial.extend_from_slice(&buf[..n]);
+
+        while self.partial.len() >= self.event_size {
+            let event: Vec<u8> = self.partial.drain(..self.event_size).collect();
+            let (Some(ev), Some(time)) = (parse_input_event(&event), parse_event_time(&event)) else {
+                continue;
+            };
+
+            let mut stats = self.stats.lock().unwrap();
+            stats.events += 1;
+            if ev.event_type() == EventType::SYN && ev.raw_code() == Syn::REPORT.raw() {
+                let tablet_us = time.as_micros() as i64;
+                stats.frame_times.push(tablet_us);
+                self.frame_end = Some(tablet_us - self.offset);
+            }
+        }
+        Ok(n)
+    }
+}
+
+#[derive(Serialize)]
+struct InputReport {
+    input: InputKind,
+    events: u64,
+    events_per_second: f64,
+    frames: usize,
+    /// Device report rate while active, from the median frame interval.
+    report_rate_hz: Option<f64>,
+    latency_us: Option<Summary>,
+    histogram: Vec<(String, usize)>,
+}
+
+#[derive(Serialize)]
+struct BenchReport {
+    model: &'static str,
+    host: String,
+    duration_s: f64,
+    clock_offset_us: i64,
+    ssh_round_trip_us: Option<Summary>,
+    inputs: Vec<InputReport>,
+}
+
+/// Run the pipeline for `duration` while the user draws and touches, then
+/// report the measurements as text or JSON.
+pub fn run(
+    config: &Config,
+    device: &'static DeviceProfile,
+    duration: Duration,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    eprintln!("Measuring SSH round trips and clock offset...");
+    let samples = clock_handshake(config)?;
+    let (offset, _) = clock_offset(&samples).ok_or("No clock samples")?;
+    let round_trips: Vec<i64> = samples.iter().map(|(sent, _, received)| received - sent).collect();
+
+    let palm = palm::create_shared_state(config);
+    let state: SharedState = Arc::new(RuntimeState::new(config, device, palm.clone()));
+
+    let inputs = [
+        (InputKind::Pen, config.run_pen(), &config.pen_device),
+        (InputKind::Touch, config.run_touch(), &config.touch_device),
+    ];
+    let mut probes = Vec::new();
+    let mut handles = Vec::new();
+
+    for (kind, _, path) in inputs.into_iter().filter(|(_, run, _)| *run) {
+        // Like dump, don't grab: the tablet keeps working during the run
+        let source = SshSource::open(path, config, false)?;
+        state.set_stream(kind, source.connection()?);
+
+        let stats = Arc::new(Mutex::new(InputStats::default()));
+        probes.push((kind, stats.clone()));
+        let mut probe = Probe {
+            inner: source,
+            event_size: device.input_event_size,
+            offset,
+            stats,
+            partial: Vec::new(),
+            frame_end: None,
+        };
+        let (config, state, palm) = (config.clone(), state.clone(), palm.clone());
+
+        handles.push(thread::spawn(move || {
+            let grabbed = state.grab();
+            let result = match kind {
+                InputKind::Pen => VirtualDevice::pen(&config, device, &state).and_then(|mut sink| {
+                    input::forward_pen(&mut probe, &mut sink, &config, device, &state, palm, grabbed)
+                }),
+                InputKind::Touch => VirtualDevice::touch(&config, device, &state).and_then(|mut sink| {
+                    input::forward_touch(&mut probe, &mut sink, &config, device, &state, palm, grabbed)
+                }),
+            };
+            if let Err(e) = result {
+                if !state.shutting_down() {
+                    log::error!("[{}] {}", kind, e);
+                }
+            }
+        }));
+    }
+
+    // Start counting once the virtual devices exist and anything queued on
+    // the tablet meanwhile has been drained
+    let connected = |status: &rm_pad::state::Status| {
+        (!config.run_pen() || status.pen.connected) && (!config.run_touch() || status.touch.connected)
+    };
+    while !connected(&state.status()) && !handles.iter().any(|h| h.is_finished()) {
+        thread::sleep(Duration::from_millis(100));
+    }
+    thread::sleep(SETTLE_TIME);
+    for (_, stats) in &probes {
+        *stats.lock().unwrap() = InputStats::default();
+    }
+
+    eprintln!("Draw and touch on the tablet for {}s...", duration.as_secs());
+    let start = Instant::now();
+    while start.elapsed() < duration && !handles.iter().all(|h| h.is_finished()) {
+        thread::sleep(Duration::from_millis(100));
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    state.request_shutdown();
+    for handle in handles {
+        let _ = handle.join();
+    }
+
+    let inputs = probes
+        .into_iter()
+        .map(|(kind, stats)| {
+            let stats = stats.lock().unwrap();
+            InputReport {
+                input: kind,
+                events: stats.events,
+                events_per_second: stats.events as f64 / elapsed,
+                frames: stats.frame_times.len(),
+                report_rate_hz: report_rate(&stats.frame_times),
+                latency_us: Summary::new(&stats.latencies),
+                histogram: histogram(&stats.latencies)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(bucket, count)| (bucket_label(bucket), count))
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let report = BenchReport {
+        model: device.name,
+        host: config.host.clone(),
+        duration_s: elapsed,
+        clock_offset_us: offset,
+        ssh_round_trip_us: Summary::new(&round_trips),
+        inputs,
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+/// Frames per second while the device reports, from the median interval
+/// between consecutive frames.
+fn report_rate(frame_times: &[i64]) -> Option<f64> {
+    let mut intervals: Vec<i64> = frame_times.windows(2).map(|w| w[1] - w[0]).filter(|&i| i > 0).collect();
+    if intervals.is_empty() {
+        return None;
+    }
+    intervals.sort_unstable();
+    Some(1_000_000.0 / percentile(&intervals, 50.0) as f64)
+}
+
+fn ms(us: i64) -> String {
+    format!("{:.2} ms", us as f64 / 1000.0)
+}
+
+fn print_summary(label: &str, summary: &Summary) {
+    println!(
+        "  {:<12} p50 {}  p90 {}  p99 {}  (min {}, max {}, {} samples)",
+        label,
+        ms(summary.p50),
+        ms(summary.p90),
+        ms(summary.p99),
+        ms(summary.min),
+        ms(summary.max),
+        summary.samples
+    );
+}
+
+fn print_report(report: &BenchReport) {
+    println!("\n{} at {}, {:.1}s", report.model, report.host, report.duration_s);
+    println!("  clock offset {} (tablet - host)", ms(report.clock_offset_us));
+    if let Some(rtt) = &report.ssh_round_trip_us {
+        print_summary("SSH RTT", rtt);
+    }
+
+    for input in &report.inputs {
+        println!("\n{}: {} events ({:.0}/s), {} frames", input.input, input.events, input.events_per_second, input.frames);
+        if let Some(rate) = input.report_rate_hz {
+            println!("  report rate  {:.0} Hz", rate);
+        }
+        let Some(latency) = &input.latency_us else {
+            println!("  no input received");
+            continue;
+        };
+        print_summary("latency", latency);
+
+        let largest = input.histogram.iter().map(|(_, count)| *count).max().unwrap_or(0).max(1);
+        for (label, count) in &input.histogram {
+            let bar = "#".repeat(count * HISTOGRAM_WIDTH / largest);
+            println!("  {:>10} {:>7}  {}", label, count, bar);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_and_histogram() {
+        let samples: Vec<i64> = (1..=100).map(|ms| ms * 1000).collect();
+        let summary = Summary::new(&samples).unwrap();
+        assert_eq!((summary.p50, summary.p90, summary.p99), (50_000, 90_000, 99_000));
+        assert_eq!((summary.min, summary.max), (1000, 100_000));
+
+        let counts = histogram(&[500, 1500, 3000, 250_000]);
+        assert_eq!(counts, [1, 1, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bucket_label(8), ">= 200 ms");
+    }
+
+    #[test]
+    fn test_clock_offset_uses_fastest_round_trip() {
+        // Tablet clock 5 ms ahead; the second sample had a slow return path
+        let samples = [(1_000, 6_500, 2_000), (3_000, 8_100, 9_000)];
+        assert_eq!(clock_offset(&samples), Some((5_000, 1_000)));
+    }
+}
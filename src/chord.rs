@@ -0,0 +1,90 @@
+//! Key chords such as `ctrl+shift+z`, used to map gestures and touch
+//! regions to keyboard shortcuts.
+
+use std::fmt;
+use std::str::FromStr;
+
+use evdevil::event::Key;
+use serde::Deserialize;
+
+/// A set of keys pressed together, in press order.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyChord(Vec<Key>);
+
+impl KeyChord {
+    pub fn keys(&self) -> &[Key] {
+        &self.0
+    }
+}
+
+/// Resolve a single key name.
+///
+/// Accepts common modifier aliases, kernel names (`KEY_F5`, `BTN_LEFT`) and
+/// kernel names without the `KEY_` prefix (`f5`, `pageup`).
+fn parse_key(name: &str) -> Option<Key> {
+    let alias = match name.to_lowercase().as_str() {
+        "ctrl" | "control" => Some(Key::KEY_LEFTCTRL),
+        "shift" => Some(Key::KEY_LEFTSHIFT),
+        "alt" => Some(Key::KEY_LEFTALT),
+        "altgr" => Some(Key::KEY_RIGHTALT),
+        "super" | "meta" | "win" => Some(Key::KEY_LEFTMETA),
+        "esc" => Some(Key::KEY_ESC),
+        "return" => Some(Key::KEY_ENTER),
+        _ => None,
+    };
+    if alias.is_some() {
+        return alias;
+    }
+
+    let upper = name.to_uppercase();
+    upper
+        .parse::<Key>()
+        .or_else(|_| format!("KEY_{}", upper).parse::<Key>())
+        .ok()
+}
+
+impl FromStr for KeyChord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = s
+            .split('+')
+            .map(str::trim)
+            .map(|name| parse_key(name).ok_or_else(|| format!("Unknown key '{}' in '{}'", name, s)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(KeyChord(keys))
+    }
+}
+
+impl TryFrom<String> for KeyChord {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<String> = self.0.iter().map(|k| format!("{:?}", k)).collect();
+        write!(f, "{}", names.join("+"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chord() {
+        let chord: KeyChord = "ctrl+shift+z".parse().unwrap();
+        assert_eq!(chord.keys(), &[Key::KEY_LEFTCTRL, Key::KEY_LEFTSHIFT, Key::KEY_Z]);
+
+        let chord: KeyChord = "KEY_PAGEUP".parse().unwrap();
+        assert_eq!(chord.keys(), &[Key::KEY_PAGEUP]);
+
+        assert!("ctrl+nosuchkey".parse::<KeyChord>().is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use rm_pad::config::Config;
+use rm_pad::control::Request;
+use rm_pad::device::DeviceProfile;
+use rm_pad::input::{GestureConfig, TouchMode};
+use rm_pad::orientation::Orientation;
+use rm_pad::palm::{Handedness, PalmMode};
+use rm_pad::state::InputKind;
+
+use crate::dump::{DumpFormat, DumpSource};
+
+#[derive(Parser)]
+#[command(name = "rm-pad")]
+#[command(about = "Forward reMarkable tablet input to your computer")]
+#[command(version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// reMarkable host (IP or hostname)
+    #[arg(long, env = "RMPAD_HOST")]
+    pub host: Option<String>,
+
+    /// SSH key path for authentication
+    #[arg(long)]
+    pub key_path: Option<String>,
+
+    /// SSH password (if set, key_path is ignored)
+    #[arg(long, env = "RMPAD_PASSWORD")]
+    pub password: Option<String>,
+
+    /// Pen input device path on reMarkable
+    #[arg(long)]
+    pub pen_device: Option<String>,
+
+    /// Touch input device path on reMarkable
+    #[arg(long)]
+    pub touch_device: Option<String>,
+
+    /// Run touch input only (no pen)
+    #[arg(long)]
+    pub touch_only: bool,
+
+    /// Run pen input only (no touch)
+    #[arg(long)]
+    pub pen_only: bool,
+
+    /// Grab input exclusively [default: true]
+    #[arg(long)]
+    pub grab_input: bool,
+
+    /// Don't grab input (tablet UI will also see input)
+    #[arg(long)]
+    pub no_grab_input: bool,
+
+    /// Disable palm rejection
+    #[arg(long)]
+    pub no_palm_rejection: bool,
+
+    /// Palm rejection grace period in milliseconds
+    #[arg(long)]
+    pub palm_grace_ms: Option<u64>,
+
+    /// Also reject palms while the pen hovers within this fraction (0.0-1.0) of its hover range
+    #[arg(long)]
+    pub palm_hover_distance: Option<f64>,
+
+    /// Palm rejection mode (global, smart)
+    #[arg(long, value_parser = clap::value_parser!(PalmMode))]
+    pub palm_mode: Option<PalmMode>,
+
+    /// Hand holding the pen, used by smart palm rejection (right, left)
+    #[arg(long, value_parser = clap::value_parser!(Handedness))]
+    pub handedness: Option<Handedness>,
+
+    /// Size in millimetres of the area next to the pen treated as palm (smart mode)
+    #[arg(long)]
+    pub palm_region_mm: Option<f64>,
+
+    /// Reject contacts whose raw ABS_MT_TOUCH_MAJOR exceeds this (smart mode)
+    #[arg(long)]
+    pub palm_touch_major_max: Option<i32>,
+
+    /// Screen orientation (portrait, landscape-right, landscape-left, inverted)
+    #[arg(long, value_parser = clap::value_parser!(Orientation))]
+    pub orientation: Option<Orientation>,
+
+    /// Follow the orientation set in the tablet UI (xochitl) instead of --orientation
+    #[arg(long)]
+    pub follow_tablet_orientation: bool,
+
+    /// Present the touch surface as a touchpad or a direct touchscreen
+    #[arg(long, value_parser = clap::value_parser!(TouchMode))]
+    pub touch_mode: Option<TouchMode>,
+
+    /// Recognise touch gestures in rm-pad (tap, scroll, pinch, swipe) instead of relying on the compositor
+    #[arg(long)]
+    pub gestures: bool,
+
+    /// Remove the grab helper, its logs and the watchdog file from the tablet on exit
+    #[arg(long)]
+    pub clean_tablet_on_exit: bool,
+
+    /// Exit when the tablet disconnects instead of waiting for it to come back
+    #[arg(long)]
+    pub exit_on_disconnect: bool,
+
+    /// Serve Prometheus metrics on this port on localhost
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    /// Path to config file
+    #[arg(long, env = "RMPAD_CONFIG")]
+    pub config: Option<PathBuf>,
+}
+
+impl Cli {
+    /// Load the configuration file and apply the flags on top of it.
+    pub fn load_config(&self, device: &DeviceProfile) -> Config {
+        let config = Config::load(self.config.as_deref(), device);
+
+        Config {
+            host: self.host.clone().unwrap_or(config.host),
+            key_path: self.key_path.clone().or(config.key_path),
+            password: self.password.clone().or(config.password),
+            pen_device: self.pen_device.clone().unwrap_or(config.pen_device),
+            touch_device: self.touch_device.clone().unwrap_or(config.touch_device),
+            touch_only: self.touch_only || config.touch_only,
+            pen_only: self.pen_only || config.pen_only,
+            grab_input: if self.no_grab_input {
+                false
+            } else {
+                self.grab_input || config.grab_input
+            },
+            no_palm_rejection: self.no_palm_rejection || config.no_palm_rejection,
+            palm_grace_ms: self.palm_grace_ms.unwrap_or(config.palm_grace_ms),
+            palm_hover_distance: self.palm_hover_distance.or(config.palm_hover_distance),
+            palm_mode: self.palm_mode.unwrap_or(config.palm_mode),
+            handedness: self.handedness.unwrap_or(config.handedness),
+            palm_region_mm: self.palm_region_mm.unwrap_or(config.palm_region_mm),
+            palm_touch_major_max: self.palm_touch_major_max.or(config.palm_touch_major_max),
+            orientation: self.orientation.unwrap_or(config.orientation),
+            follow_tablet_orientation: self.follow_tablet_orientation
+                || config.follow_tablet_orientation,
+            exit_on_disconnect: self.exit_on_disconnect || config.exit_on_disconnect,
+            clean_tablet_on_exit: self.clean_tablet_on_exit || config.clean_tablet_on_exit,
+            metrics_port: self.metrics_port.or(config.metrics_port),
+            touch_mode: self.touch_mode.unwrap_or(config.touch_mode),
+            gestures: GestureConfig {
+                enabled: self.gestures || config.gestures.enabled,
+                ..config.gestures
+            },
+            ..config
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Dump raw input events for debugging
+    Dump {
+        /// Device to dump: "touch", "pen" or "both"
+        #[arg(value_parser = clap::value_parser!(DumpSource))]
+        device: DumpSource,
+
+        /// Output format (text, json, csv, evtest)
+        #[arg(long, default_value_t, value_parser = clap::value_parser!(DumpFormat))]
+        format: DumpFormat,
+
+        /// Print one line per SYN_REPORT frame instead of one per event
+        #[arg(long)]
+        frames: bool,
+
+        /// Show positions and tilt after the orientation or transform, as rm-pad emits them
+        #[arg(long)]
+        decoded: bool,
+
+        /// Show what the running instance writes to its virtual devices instead of the tablet input
+        #[arg(long)]
+        output: bool,
+    },
+
+    /// Record raw pen and touch events from the tablet to a file
+    Record {
+        /// File to write the recording to
+        file: PathBuf,
+    },
+
+    /// Play a recording through the forwarding pipeline, without a tablet
+    Replay {
+        /// Recording made with `rm-pad record`
+        file: PathBuf,
+
+        /// Playback speed factor (0 replays as fast as possible)
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+
+    /// Measure latency, throughput and report rates while you use the tablet
+    Bench {
+        /// How long to measure, in seconds
+        #[arg(long, default_value_t = 20)]
+        duration: u64,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Install libwacom and udev hwdb files describing the virtual devices
+    InstallDesktopIntegration {
+        /// Directory to install below (files go to <prefix>/libwacom and <prefix>/udev/hwdb.d)
+        #[arg(long, default_value = "/etc")]
+        prefix: PathBuf,
+    },
+
+    /// Remove files written by install-desktop-integration
+    UninstallDesktopIntegration {
+        /// Directory the files were installed below
+        #[arg(long, default_value = "/etc")]
+        prefix: PathBuf,
+    },
+
+    /// Check the host and tablet setup and suggest fixes
+    Doctor,
+
+    /// Manage rm-pad's files on the tablet
+    Tablet {
+        #[command(subcommand)]
+        command: TabletCommand,
+    },
+
+    /// Control a running instance through its control socket
+    Ctl {
+        #[command(subcommand)]
+        request: CtlCommand,
+    },
+}
+
+/// The control socket requests `rm-pad ctl` can send.
+#[derive(Clone, Subcommand)]
+pub enum CtlCommand {
+    /// Show connection, forwarding and palm rejection status
+    Status,
+    /// Stop forwarding input, keeping the tablet grabbed
+    Pause,
+    /// Resume forwarding input
+    Resume,
+    /// Give the tablet input back to the tablet UI
+    Release,
+    /// Grab the tablet input again
+    Grab,
+    /// Switch orientation (portrait, landscape-right, landscape-left, inverted)
+    Orientation {
+        #[arg(value_parser = clap::value_parser!(Orientation))]
+        orientation: Orientation,
+    },
+    /// Start forwarding pen or touch input
+    Enable {
+        #[arg(value_parser = clap::value_parser!(InputKind))]
+        input: InputKind,
+    },
+    /// Stop forwarding pen or touch input
+    Disable {
+        #[arg(value_parser = clap::value_parser!(InputKind))]
+        input: InputKind,
+    },
+}
+
+impl From<CtlCommand> for Request {
+    fn from(command: CtlCommand) -> Self {
+        match command {
+            CtlCommand::Status => Request::Status,
+            CtlCommand::Pause => Request::Pause,
+            CtlCommand::Resume => Request::Resume,
+            CtlCommand::Release => Request::Release,
+            CtlCommand::Grab => Request::Grab,
+            CtlCommand::Orientation { orientation } => Request::Orientation { orientation },
+            CtlCommand::Enable { input } => Request::Enable { input },
+            CtlCommand::Disable { input } => Request::Disable { input },
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum TabletCommand {
+    /// Remove the grab helper, its logs, upload leftovers and the watchdog file
+    Clean,
+
+    /// Print the grab helper's log from the tablet
+    Logs {
+        /// Keep printing new lines as they are written
+        #[arg(short, long)]
+        follow: bool,
+    },
+}
@@ -1,7 +1,10 @@
 use serde::Deserialize;
 use std::path::{Path, PathBuf};
 
+use crate::input::{ExclusionConfig, GestureConfig, TouchMode, TouchRegion};
 use crate::orientation::Orientation;
+use crate::palm::{Handedness, PalmMode};
+use crate::transform::{AffineTransform, Crop};
 
 const DEFAULT_HOST: &str = "10.11.99.1";
 
@@ -23,8 +26,34 @@ pub struct FileConfig {
     #[serde(default)]
     pub no_palm_rejection: bool,
     pub palm_grace_ms: Option<u64>,
+    pub palm_hover_distance: Option<f64>,
+    #[serde(default)]
+    pub palm_mode: PalmMode,
+    #[serde(default)]
+    pub handedness: Handedness,
+    pub palm_touch_major_max: Option<i32>,
+    pub palm_region_mm: Option<f64>,
     #[serde(default)]
     pub orientation: Orientation,
+    #[serde(default)]
+    pub follow_tablet_orientation: bool,
+    #[serde(default)]
+    pub exit_on_disconnect: bool,
+    #[serde(default)]
+    pub clean_tablet_on_exit: bool,
+    pub metrics_port: Option<u16>,
+    #[serde(default)]
+    pub touch_mode: TouchMode,
+    #[serde(default)]
+    pub gestures: GestureConfig,
+    #[serde(default)]
+    pub touch_exclusion: ExclusionConfig,
+    #[serde(default)]
+    pub touch_regions: Vec<TouchRegion>,
+    pub pen_transform: Option<AffineTransform>,
+    pub touch_transform: Option<AffineTransform>,
+    pub pen_crop: Option<Crop>,
+    pub touch_crop: Option<Crop>,
 }
 
 impl Default for FileConfig {
@@ -40,7 +69,24 @@ impl Default for FileConfig {
             pen_only: false,
             no_palm_rejection: false,
             palm_grace_ms: None,
+            palm_hover_distance: None,
+            palm_mode: PalmMode::default(),
+            handedness: Handedness::default(),
+            palm_touch_major_max: None,
+            palm_region_mm: None,
             orientation: Orientation::default(),
+            follow_tablet_orientation: false,
+            exit_on_disconnect: false,
+            clean_tablet_on_exit: false,
+            metrics_port: None,
+            touch_mode: TouchMode::default(),
+            gestures: GestureConfig::default(),
+            touch_exclusion: ExclusionConfig::default(),
+            touch_regions: Vec::new(),
+            pen_transform: None,
+            touch_transform: None,
+            pen_crop: None,
+            touch_crop: None,
         }
     }
 }
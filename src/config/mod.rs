@@ -1,12 +1,15 @@
-mod cli;
 mod file;
 
-pub use cli::{Cli, Command};
-
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::device::DeviceProfile;
+use crate::input::{ExclusionConfig, GestureConfig, TouchMode, TouchRegion};
 use crate::orientation::Orientation;
+use crate::palm::{Handedness, PalmConfig, PalmMode};
+use crate::transform::{AffineTransform, Crop};
+
+use file::FileConfig;
 
 /// Authentication method for SSH connection.
 #[derive(Clone)]
@@ -28,44 +31,73 @@ pub struct Config {
     pub grab_input: bool,
     pub no_palm_rejection: bool,
     pub palm_grace_ms: u64,
+    pub palm_hover_distance: Option<f64>,
+    pub palm_mode: PalmMode,
+    pub handedness: Handedness,
+    pub palm_touch_major_max: Option<i32>,
+    pub palm_region_mm: f64,
     pub orientation: Orientation,
+    pub follow_tablet_orientation: bool,
+    pub exit_on_disconnect: bool,
+    pub clean_tablet_on_exit: bool,
+    pub metrics_port: Option<u16>,
+    pub touch_mode: TouchMode,
+    pub gestures: GestureConfig,
+    pub touch_exclusion: ExclusionConfig,
+    pub touch_regions: Vec<TouchRegion>,
+    pub pen_transform: Option<AffineTransform>,
+    pub touch_transform: Option<AffineTransform>,
+    /// Crops of the transforms, kept to check that each has a transform.
+    pub pen_crop: Option<Crop>,
+    pub touch_crop: Option<Crop>,
 }
 
 impl Config {
-    /// Load configuration by merging TOML file with CLI overrides.
-    pub fn load(cli: &Cli, device: &DeviceProfile) -> Self {
-        let file_config = cli
-            .config
-            .as_ref()
-            .and_then(|p| file::load_from_path(p))
+    /// Defaults for `device`, as if no configuration file were present.
+    pub fn new(device: &DeviceProfile) -> Self {
+        Self::from_file_config(FileConfig::default(), device)
+    }
+
+    /// Load the TOML file at `path`, or the first one found in the default
+    /// locations, filling in defaults for `device`.
+    pub fn load(path: Option<&Path>, device: &DeviceProfile) -> Self {
+        let file_config = path
+            .and_then(file::load_from_path)
             .or_else(file::load_from_default_paths)
             .unwrap_or_default();
+        Self::from_file_config(file_config, device)
+    }
 
+    fn from_file_config(file_config: FileConfig, device: &DeviceProfile) -> Self {
         Self {
-            host: cli.host.clone().unwrap_or(file_config.host),
-            key_path: cli.key_path.clone().or(file_config.key_path),
-            password: cli.password.clone().or(file_config.password),
-            pen_device: cli
-                .pen_device
-                .clone()
-                .unwrap_or_else(|| file_config.pen_device.unwrap_or(device.pen_device.into())),
-            touch_device: cli
-                .touch_device
-                .clone()
-                .unwrap_or_else(|| file_config.touch_device.unwrap_or(device.touch_device.into())),
-            touch_only: cli.touch_only || file_config.touch_only,
-            pen_only: cli.pen_only || file_config.pen_only,
-            grab_input: if cli.no_grab_input {
-                false
-            } else {
-                cli.grab_input || file_config.grab_input
-            },
-            no_palm_rejection: cli.no_palm_rejection || file_config.no_palm_rejection,
-            palm_grace_ms: cli
-                .palm_grace_ms
-                .or(file_config.palm_grace_ms)
-                .unwrap_or(500),
-            orientation: cli.orientation.unwrap_or(file_config.orientation),
+            host: file_config.host,
+            key_path: file_config.key_path,
+            password: file_config.password,
+            pen_device: file_config.pen_device.unwrap_or(device.pen_device.into()),
+            touch_device: file_config.touch_device.unwrap_or(device.touch_device.into()),
+            touch_only: file_config.touch_only,
+            pen_only: file_config.pen_only,
+            grab_input: file_config.grab_input,
+            no_palm_rejection: file_config.no_palm_rejection,
+            palm_grace_ms: file_config.palm_grace_ms.unwrap_or(500),
+            palm_hover_distance: file_config.palm_hover_distance,
+            palm_mode: file_config.palm_mode,
+            handedness: file_config.handedness,
+            palm_touch_major_max: file_config.palm_touch_major_max,
+            palm_region_mm: file_config.palm_region_mm.unwrap_or(80.0),
+            orientation: file_config.orientation,
+            follow_tablet_orientation: file_config.follow_tablet_orientation,
+            exit_on_disconnect: file_config.exit_on_disconnect,
+            clean_tablet_on_exit: file_config.clean_tablet_on_exit,
+            metrics_port: file_config.metrics_port,
+            touch_mode: file_config.touch_mode,
+            gestures: file_config.gestures,
+            touch_exclusion: file_config.touch_exclusion,
+            touch_regions: file_config.touch_regions,
+            pen_transform: file_config.pen_transform.map(|t| t.with_crop(file_config.pen_crop)),
+            touch_transform: file_config.touch_transform.map(|t| t.with_crop(file_config.touch_crop)),
+            pen_crop: file_config.pen_crop,
+            touch_crop: file_config.touch_crop,
         }
     }
 
@@ -77,6 +109,19 @@ impl Config {
         Auth::Key(expand_tilde(path))
     }
 
+    pub fn palm_config(&self, device: &DeviceProfile) -> PalmConfig {
+        PalmConfig {
+            mode: self.palm_mode,
+            grace: Duration::from_millis(self.palm_grace_ms),
+            handedness: self.handedness,
+            touch_major_max: self.palm_touch_major_max,
+            region_mm: self.palm_region_mm,
+            hover_distance: self
+                .palm_hover_distance
+                .map(|fraction| (fraction * device.pen_distance_max as f64) as i32),
+        }
+    }
+
     pub fn run_pen(&self) -> bool {
         !self.touch_only
     }
@@ -92,6 +137,30 @@ impl Config {
         if !self.run_pen() && !self.run_touch() {
             return Err("No input device enabled");
         }
+        if self
+            .palm_hover_distance
+            .is_some_and(|d| !(0.0..=1.0).contains(&d))
+        {
+            return Err("palm_hover_distance must be between 0.0 and 1.0");
+        }
+        if !self.touch_regions.iter().all(TouchRegion::is_valid) {
+            return Err("Each [[touch_regions]] entry needs exactly one of keys, button or command");
+        }
+        if [self.pen_transform, self.touch_transform]
+            .iter()
+            .flatten()
+            .any(|t| !t.is_invertible())
+        {
+            return Err("pen_transform and touch_transform must not collapse the area to a line");
+        }
+        if (self.pen_crop.is_some() && self.pen_transform.is_none())
+            || (self.touch_crop.is_some() && self.touch_transform.is_none())
+        {
+            return Err("pen_crop and touch_crop need a pen_transform or touch_transform to go with them");
+        }
+        if [self.pen_crop, self.touch_crop].iter().flatten().any(|c| !c.is_valid()) {
+            return Err("pen_crop and touch_crop must be [x_min, y_min, x_max, y_max] with each minimum below its maximum");
+        }
         Ok(())
     }
 }
@@ -0,0 +1,219 @@
+//! JSON-lines control socket for a running instance, and the `rm-pad ctl` client.
+//!
+//! Each line sent to `$XDG_RUNTIME_DIR/rm-pad.sock` is a request such as
+//! `{"cmd":"pause"}` or `{"cmd":"enable","input":"touch"}`; each gets one
+//! line back, e.g. `{"ok":true}`.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::orientation::Orientation;
+use crate::state::{InputKind, SharedState, Status};
+
+const SOCKET_NAME: &str = "rm-pad.sock";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case")]
+pub enum Request {
+    /// Show connection, forwarding and palm rejection status
+    Status,
+    /// Stop forwarding input, keeping the tablet grabbed
+    Pause,
+    /// Resume forwarding input
+    Resume,
+    /// Give the tablet input back to the tablet UI
+    Release,
+    /// Grab the tablet input again
+    Grab,
+    /// Switch orientation
+    Orientation { orientation: Orientation },
+    /// Start forwarding pen or touch input
+    Enable { input: InputKind },
+    /// Stop forwarding pen or touch input
+    Disable { input: InputKind },
+    /// Stream the frames written to the virtual devices, one JSON line per
+    /// frame after the response. Used by `rm-pad dump --output`.
+    WatchOutput { inputs: Vec<InputKind> },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+impl Response {
+    fn ok() -> Self {
+        Self { ok: true, ..Default::default() }
+    }
+
+    fn error(message: impl Into<String>) -> Self {
+        Self { ok: false, error: Some(message.into()), ..Default::default() }
+    }
+}
+
+/// Path of the control socket. Only `$XDG_RUNTIME_DIR` is used, since it
+/// is private to the user; a shared directory such as `/tmp` would let other
+/// local users reach the socket.
+pub fn socket_path() -> Result<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| PathBuf::from(dir).join(SOCKET_NAME))
+        .ok_or_else(|| Error::Control("XDG_RUNTIME_DIR is not set, so there is no control socket".into()))
+}
+
+/// Listen on the control socket, serving each client on its own thread.
+/// Returns the socket's path, to remove it on shutdown.
+pub fn spawn_control_server(state: &SharedState) -> Result<PathBuf> {
+    let path = socket_path()?;
+
+    if path.exists() {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use; is another rm-pad running?", path.display()),
+            )
+            .into());
+        }
+        // Left behind by an instance that didn't exit cleanly
+        std::fs::remove_file(&path)?;
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    log::info!("Control socket listening on {}", path.display());
+
+    let state = state.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let state = state.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve_client(stream, &state) {
+                            log::debug!("Control client error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => log::warn!("Control socket accept failed: {}", e),
+            }
+        }
+    });
+
+    Ok(path)
+}
+
+fn serve_client(stream: UnixStream, state: &SharedState) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = serde_json::from_str::<Request>(&line);
+        if let Ok(Request::WatchOutput { inputs }) = request {
+            return stream_output(writer, state, &inputs);
+        }
+        let response = match request {
+            Ok(request) => handle_request(request, state),
+            Err(e) => Response::error(format!("Invalid request: {}", e)),
+        };
+
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+/// Send frames written to the virtual devices until the client goes away.
+fn stream_output(mut writer: UnixStream, state: &SharedState, inputs: &[InputKind]) -> io::Result<()> {
+    let frames = state.tap_output(inputs);
+    serde_json::to_writer(&mut writer, &Response::ok())?;
+    writer.write_all(b"\n")?;
+
+    for frame in frames {
+        serde_json::to_writer(&mut writer, &frame)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn handle_request(request: Request, state: &SharedState) -> Response {
+    log::debug!("Control request: {:?}", request);
+
+    match request {
+        Request::Status => {
+            return Response { status: Some(state.status()), ..Response::ok() };
+        }
+        Request::Pause => state.set_paused(true),
+        Request::Resume => state.set_paused(false),
+        Request::Release => state.set_grab(false),
+        Request::Grab => state.set_grab(true),
+        Request::Orientation { orientation } => state.set_orientation(orientation),
+        Request::Enable { input } => state.set_enabled(input, true),
+        Request::Disable { input } => state.set_enabled(input, false),
+        Request::WatchOutput { .. } => return Response::error("watch-output must be the only request"),
+    }
+    Response::ok()
+}
+
+/// Send `request` to the running instance. Returns its response and the
+/// connection, for requests that stream more lines after it.
+pub fn send_request(
+    request: &Request,
+) -> Result<(Response, BufReader<UnixStream>)> {
+    let path = socket_path()?;
+    let stream = UnixStream::connect(&path)
+        .map_err(|e| Error::Control(format!("Cannot connect to {} ({}); is rm-pad running?", path.display(), e)))?;
+
+    let mut writer = stream.try_clone()?;
+    serde_json::to_writer(&mut writer, request)?;
+    writer.write_all(b"\n")?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let response: Response = serde_json::from_str(&line)?;
+
+    if !response.ok {
+        return Err(Error::Control(response.error.unwrap_or_else(|| "Request failed".into())));
+    }
+    Ok((response, reader))
+}
+
+/// Send one request to the running instance and print the result.
+pub fn run_client(request: &Request) -> Result<()> {
+    let (response, _) = send_request(request)?;
+    if let Some(status) = response.status {
+        println!("{}", serde_json::to_string_pretty(&status)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_format() {
+        let request: Request = serde_json::from_str(r#"{"cmd":"disable","input":"touch"}"#).unwrap();
+        assert!(matches!(request, Request::Disable { input: InputKind::Touch }));
+
+        let request: Request =
+            serde_json::from_str(r#"{"cmd":"orientation","orientation":"portrait"}"#).unwrap();
+        assert!(matches!(request, Request::Orientation { orientation: Orientation::Portrait }));
+
+        assert_eq!(serde_json::to_string(&Request::Pause).unwrap(), r#"{"cmd":"pause"}"#);
+    }
+}
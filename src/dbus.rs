@@ -0,0 +1,160 @@
+//! Session bus interface for desktop widgets.
+//!
+//! Exposes `io.github.rmpad` at `/io/github/rmpad` with the runtime state as
+//! properties, methods mirroring the control socket, and `Connected` /
+//! `Disconnected` signals when the pen or touch stream comes and goes.
+
+use std::thread;
+use std::time::Duration;
+
+use zbus::blocking::connection;
+use zbus::blocking::object_server::InterfaceRef;
+use zbus::interface;
+use zbus::object_server::SignalEmitter;
+
+use crate::orientation::Orientation;
+use crate::state::{InputKind, SharedState, Status};
+
+const BUS_NAME: &str = "io.github.rmpad";
+const OBJECT_PATH: &str = "/io/github/rmpad";
+
+/// How often the state is checked for changes to signal.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+struct RmPad {
+    state: SharedState,
+}
+
+#[interface(name = "io.github.rmpad")]
+impl RmPad {
+    /// Stop forwarding input, keeping the tablet grabbed.
+    fn pause(&self) {
+        self.state.set_paused(true);
+    }
+
+    fn resume(&self) {
+        self.state.set_paused(false);
+    }
+
+    /// Grab the tablet input so the tablet UI doesn't see it.
+    fn grab(&self) {
+        self.state.set_grab(true);
+    }
+
+    /// Give the tablet input back to the tablet UI.
+    fn release(&self) {
+        self.state.set_grab(false);
+    }
+
+    fn set_orientation(&self, orientation: &str) -> zbus::fdo::Result<()> {
+        let orientation: Orientation = orientation.parse().map_err(zbus::fdo::Error::InvalidArgs)?;
+        self.state.set_orientation(orientation);
+        Ok(())
+    }
+
+    #[zbus(property)]
+    fn model(&self) -> String {
+        self.state.status().model
+    }
+
+    #[zbus(property)]
+    fn orientation(&self) -> String {
+        self.state.orientation().to_string()
+    }
+
+    #[zbus(property)]
+    fn pen_connected(&self) -> bool {
+        self.state.status().pen.connected
+    }
+
+    #[zbus(property)]
+    fn touch_connected(&self) -> bool {
+        self.state.status().touch.connected
+    }
+
+    #[zbus(property)]
+    fn paused(&self) -> bool {
+        self.state.status().paused
+    }
+
+    #[zbus(property)]
+    fn grabbed(&self) -> bool {
+        self.state.grab()
+    }
+
+    /// `input` is `pen` or `touch`.
+    #[zbus(signal)]
+    async fn connected(emitter: &SignalEmitter<'_>, input: &str) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn disconnected(emitter: &SignalEmitter<'_>, input: &str) -> zbus::Result<()>;
+}
+
+/// Register the interface on the session bus and keep its signals and
+/// property notifications in sync with `state`.
+pub fn spawn_dbus_service(state: &SharedState) -> zbus::Result<()> {
+    let connection = connection::Builder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, RmPad { state: state.clone() })?
+        .build()?;
+    log::info!("D-Bus interface available as {}", BUS_NAME);
+
+    let iface: InterfaceRef<RmPad> = connection.object_server().interface(OBJECT_PATH)?;
+    let state = state.clone();
+
+    thread::spawn(move || {
+        // Keep the connection alive for as long as the thread runs
+        let _connection = connection;
+        let mut previous = state.status();
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let current = state.status();
+            if let Err(e) = zbus::block_on(notify_changes(&iface, &previous, &current)) {
+                log::warn!("D-Bus notification failed: {}", e);
+            }
+            previous = current;
+        }
+    });
+
+    Ok(())
+}
+
+async fn notify_changes(
+    iface: &InterfaceRef<RmPad>,
+    previous: &Status,
+    current: &Status,
+) -> zbus::Result<()> {
+    let emitter = iface.signal_emitter();
+    let rmpad = iface.get();
+
+    for (kind, was, is) in [
+        (InputKind::Pen, previous.pen.connected, current.pen.connected),
+        (InputKind::Touch, previous.touch.connected, current.touch.connected),
+    ] {
+        if was == is {
+            continue;
+        }
+        let input = kind.to_string();
+        if is {
+            RmPad::connected(emitter, &input).await?;
+        } else {
+            RmPad::disconnected(emitter, &input).await?;
+        }
+        match kind {
+            InputKind::Pen => rmpad.pen_connected_changed(emitter).await?,
+            InputKind::Touch => rmpad.touch_connected_changed(emitter).await?,
+        }
+    }
+
+    if previous.orientation != current.orientation {
+        rmpad.orientation_changed(emitter).await?;
+    }
+    if previous.paused != current.paused {
+        rmpad.paused_changed(emitter).await?;
+    }
+    if previous.grab != current.grab {
+        rmpad.grabbed_changed(emitter).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! Desktop integration files for the virtual devices.
+//!
+//! GNOME and KDE only offer tablet settings (such as output mapping) for
+//! devices that libwacom knows about. This module writes a libwacom
+//! `.tablet` description for the virtual pen, sized from the detected
+//! `DeviceProfile`, and a udev hwdb snippet that tags both virtual devices
+//! so udev classifies them correctly.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rm_pad::device::DeviceProfile;
+use rm_pad::input::{
+    PEN_DEVICE_NAME, PEN_PRODUCT_ID, TOUCH_DEVICE_NAME, TOUCH_PRODUCT_ID, VIRTUAL_VENDOR_ID,
+};
+
+/// File name of the libwacom tablet description.
+const TABLET_FILE: &str = "rm-pad.tablet";
+
+/// File name of the udev hwdb snippet.
+const HWDB_FILE: &str = "66-rm-pad.hwdb";
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// Install locations below a prefix (normally `/etc`).
+struct Paths {
+    tablet: PathBuf,
+    hwdb: PathBuf,
+}
+
+impl Paths {
+    fn new(prefix: &Path) -> Self {
+        Self {
+            tablet: prefix.join("libwacom").join(TABLET_FILE),
+            hwdb: prefix.join("udev").join("hwdb.d").join(HWDB_FILE),
+        }
+    }
+}
+
+/// Write the libwacom description and hwdb snippet below `prefix`.
+pub fn install(
+    device: &DeviceProfile,
+    prefix: &Path,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let paths = Paths::new(prefix);
+
+    write_file(&paths.tablet, &tablet_description(device))?;
+    println!("Wrote {}", paths.tablet.display());
+
+    write_file(&paths.hwdb, &hwdb_entries())?;
+    println!("Wrote {}", paths.hwdb.display());
+
+    println!("\nReload the hardware database and re-trigger input devices:");
+    println!("  sudo systemd-hwdb update && sudo udevadm trigger -s input");
+    println!("Restart rm-pad afterwards so the virtual devices are recreated.");
+    Ok(())
+}
+
+/// Remove files previously written by [`install`].
+pub fn uninstall(prefix: &Path) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let paths = Paths::new(prefix);
+
+    for path in [&paths.tablet, &paths.hwdb] {
+        match fs::remove_file(path) {
+            Ok(()) => println!("Removed {}", path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                println!("Not installed: {}", path.display())
+            }
+            Err(e) => return Err(with_path_hint(e, path)),
+        }
+    }
+
+    println!("\nReload the hardware database:");
+    println!("  sudo systemd-hwdb update && sudo udevadm trigger -s input");
+    Ok(())
+}
+
+fn write_file(path: &Path, content: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| with_path_hint(e, dir))?;
+    }
+    fs::write(path, content).map_err(|e| with_path_hint(e, path))?;
+    Ok(())
+}
+
+fn with_path_hint(e: io::Error, path: &Path) -> Box<dyn std::error::Error + Send + Sync> {
+    if e.kind() == io::ErrorKind::PermissionDenied {
+        format!("{}: {} (try again with sudo, or pass --prefix)", path.display(), e).into()
+    } else {
+        format!("{}: {}", path.display(), e).into()
+    }
+}
+
+/// libwacom `.tablet` description of the virtual pen.
+///
+/// The size is given in whole inches, as libwacom expects. The tablet is not
+/// marked as integrated in a display, so desktops offer to map it to any
+/// monitor.
+fn tablet_description(device: &DeviceProfile) -> String {
+    let (width_mm, height_mm) = device.pen_size_mm();
+
+    format!(
+        "# libwacom description for the rm-pad virtual pen ({model}).\n\
+         # Generated by `rm-pad install-desktop-integration`.\n\
+         \n\
+         [Device]\n\
+         Name={model} (rm-pad)\n\
+         DeviceMatch=usb|{vid:04x}|{pid:04x}|{name}\n\
+         Width={width}\n\
+         Height={height}\n\
+         Styli=@generic-no-eraser;\n\
+         \n\
+         [Features]\n\
+         Stylus=true\n\
+         Reversible=false\n\
+         Touch=false\n\
+         Buttons=0\n",
+        model = device.name,
+        vid = VIRTUAL_VENDOR_ID,
+        pid = PEN_PRODUCT_ID,
+        name = PEN_DEVICE_NAME,
+        width = (width_mm / MM_PER_INCH).round() as u32,
+        height = (height_mm / MM_PER_INCH).round() as u32,
+    )
+}
+
+/// udev hwdb entries that classify and tag both virtual devices.
+fn hwdb_entries() -> String {
+    format!(
+        "# rm-pad virtual input devices.\n\
+         # Generated by `rm-pad install-desktop-integration`.\n\
+         \n\
+         # {pen}\n\
+         id-input:modalias:input:b0003v{vid:04X}p{pen_pid:04X}*\n\
+         \x20ID_INPUT=1\n\
+         \x20ID_INPUT_TABLET=1\n\
+         \x20ID_INPUT_JOYSTICK=0\n\
+         \x20RM_PAD_DEVICE=pen\n\
+         \n\
+         # {touch}\n\
+         id-input:modalias:input:b0003v{vid:04X}p{touch_pid:04X}*\n\
+         \x20ID_INPUT=1\n\
+         \x20ID_INPUT_JOYSTICK=0\n\
+         \x20RM_PAD_DEVICE=touch\n",
+        pen = PEN_DEVICE_NAME,
+        touch = TOUCH_DEVICE_NAME,
+        vid = VIRTUAL_VENDOR_ID,
+        pen_pid = PEN_PRODUCT_ID,
+        touch_pid = TOUCH_PRODUCT_ID,
+    )
+}
@@ -3,13 +3,14 @@ mod rmpp;
 
 use std::io::Read;
 
+use crate::error::{Error, Result};
+
 pub use rm2::RM2;
 pub use rmpp::RMPP;
 
 /// Device-specific parameters for input handling.
 #[derive(Debug, Clone, Copy)]
 pub struct DeviceProfile {
-    #[allow(dead_code)]
     pub name: &'static str,
 
     // Raw input_event size on the device (bytes)
@@ -21,18 +22,31 @@ pub struct DeviceProfile {
     pub pen_pressure_max: i32,
     pub pen_distance_max: i32,
     pub pen_tilt_range: i32,
+    // Pen digitizer units per millimetre
+    pub pen_resolution: i32,
 
     // Touch screen dimensions
     pub touch_x_max: i32,
     pub touch_y_max: i32,
     pub touch_resolution: i32,
 
+    // Touch contact shape ranges
+    pub touch_major_max: i32,
+    pub touch_orientation_max: i32,
+    pub touch_pressure_max: i32,
+
     // Default device paths
     pub pen_device: &'static str,
     pub touch_device: &'static str,
 }
 
 impl DeviceProfile {
+    /// Physical size of the pen digitizer in millimetres, in raw (unrotated) axes.
+    pub fn pen_size_mm(&self) -> (f64, f64) {
+        let res = self.pen_resolution as f64;
+        (self.pen_x_max as f64 / res, self.pen_y_max as f64 / res)
+    }
+
     /// Get profile for the current device.
     /// 
     /// Defaults to RM2. For actual detection, use `detect_via_ssh()`.
@@ -40,11 +54,16 @@ impl DeviceProfile {
         &RM2
     }
 
+    /// Look up a profile by its `name`, e.g. from a recording.
+    pub fn by_name(name: &str) -> Option<&'static Self> {
+        [&RM2, &RMPP].into_iter().find(|profile| profile.name == name)
+    }
+
     /// Detect device via SSH connection.
     /// 
     /// Reads the device model from /proc/device-tree/model on the remote device.
     /// Returns an error if the model cannot be detected or is unsupported.
-    pub fn detect_via_ssh(session: &ssh2::Session) -> Result<&'static Self, Box<dyn std::error::Error + Send + Sync>> {
+    pub fn detect_via_ssh(session: &ssh2::Session) -> Result<&'static Self> {
         let mut channel = session.channel_session()?;
         channel.exec("cat /proc/device-tree/model")?;
 
@@ -55,12 +74,12 @@ impl DeviceProfile {
 
         let status = channel.exit_status()?;
         if status != 0 {
-            return Err(format!("Failed to read device model (exit status {})", status).into());
+            return Err(Error::RemoteCommand { action: "read device model", status });
         }
 
         let model = output.trim();
         if model.is_empty() {
-            return Err("Device model is empty".into());
+            return Err(Error::UnsupportedModel(String::new()));
         }
 
         log::debug!("Detected remote device model: {}", model);
@@ -77,6 +96,6 @@ impl DeviceProfile {
             return Ok(&RM2);
         }
 
-        Err(format!("Unsupported device model: '{}'", model).into())
+        Err(Error::UnsupportedModel(model.to_string()))
     }
 }
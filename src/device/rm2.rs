@@ -12,12 +12,19 @@ pub const RM2: DeviceProfile = DeviceProfile {
     pen_pressure_max: 4095,
     pen_distance_max: 255,
     pen_tilt_range: 6400,
+    // ~210×157 mm active area
+    pen_resolution: 100,
 
     // Touch screen: 1872×1404 display, ~210×158 mm → ~9 units/mm
     touch_x_max: 1403,
     touch_y_max: 1871,
     touch_resolution: 9,
 
+    // Contact size, orientation (±quarter turn) and pressure ranges
+    touch_major_max: 255,
+    touch_orientation_max: 127,
+    touch_pressure_max: 255,
+
     // Default device paths
     pen_device: "/dev/input/event1",
     touch_device: "/dev/input/event2",
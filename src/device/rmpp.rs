@@ -18,11 +18,17 @@ pub const RMPP: DeviceProfile = DeviceProfile {
     pen_pressure_max: 4096,
     pen_distance_max: 65535,
     pen_tilt_range: 9000,
+    // ~179×240 mm active area
+    pen_resolution: 63,
 
     touch_x_max: 2064,
     touch_y_max: 2832,
     touch_resolution: 9,
 
+    touch_major_max: 255,
+    touch_orientation_max: 127,
+    touch_pressure_max: 255,
+
     pen_device: "/dev/input/event2",
     touch_device: "/dev/input/event3",
 };
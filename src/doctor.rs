@@ -0,0 +1,258 @@
+//! `rm-pad doctor`: check the host and tablet setup step by step.
+//!
+//! Each check reports pass, warn, fail or skip (when an earlier check it
+//! depends on failed), and failures come with a suggested fix.
+
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use ssh2::Session;
+
+use rm_pad::config::{Auth, Config};
+use rm_pad::device::DeviceProfile;
+use rm_pad::grab;
+use rm_pad::ssh;
+
+use crate::cli::Cli;
+
+const UINPUT_PATH: &str = "/dev/uinput";
+const UINPUT_GROUP: &str = "uinput";
+const UDEV_RULE_PATHS: [&str; 2] = [
+    "/etc/udev/rules.d/50-uinput.rules",
+    "/usr/lib/udev/rules.d/50-uinput.rules",
+];
+
+enum Outcome {
+    Pass(String),
+    Warn(String, String),
+    Fail(String, String),
+    Skip,
+}
+
+struct Check {
+    name: &'static str,
+    outcome: Outcome,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, outcome: Outcome::Pass(detail.into()) }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { name, outcome: Outcome::Warn(detail.into(), fix.into()) }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, fix: impl Into<String>) -> Self {
+        Self { name, outcome: Outcome::Fail(detail.into(), fix.into()) }
+    }
+
+    fn skip(name: &'static str) -> Self {
+        Self { name, outcome: Outcome::Skip }
+    }
+}
+
+/// Run all checks and print the results. Exits with status 1 if any failed.
+pub fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let config = cli.load_config(DeviceProfile::current());
+    let mut checks = vec![check_uinput(), check_udev_rule()];
+    checks.extend(check_tablet(cli, &config));
+
+    print_table(&checks);
+
+    if checks.iter().any(|c| matches!(c.outcome, Outcome::Fail(..))) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn check_uinput() -> Check {
+    const NAME: &str = "uinput access";
+
+    match OpenOptions::new().write(true).open(UINPUT_PATH) {
+        Ok(_) => Check::pass(NAME, format!("{} is writable", UINPUT_PATH)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Check::fail(
+            NAME,
+            format!("{} does not exist", UINPUT_PATH),
+            "Load the uinput module: sudo modprobe uinput",
+        ),
+        Err(e) => {
+            let fix = match uinput_group_membership() {
+                None => "Create the group: sudo groupadd -f uinput, then add yourself: sudo usermod -aG uinput $USER".into(),
+                Some(false) => "Add yourself to the group: sudo usermod -aG uinput $USER, then log out and back in".into(),
+                Some(true) => format!(
+                    "You are in the {} group; install the udev rule and run: sudo udevadm control --reload-rules && sudo udevadm trigger",
+                    UINPUT_GROUP
+                ),
+            };
+            Check::fail(NAME, format!("Cannot open {}: {}", UINPUT_PATH, e), fix)
+        }
+    }
+}
+
+/// Whether this process is in the uinput group, or `None` if it doesn't exist.
+fn uinput_group_membership() -> Option<bool> {
+    let groups = std::fs::read_to_string("/etc/group").ok()?;
+    let gid = groups.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        (fields.next()? == UINPUT_GROUP).then(|| fields.nth(1))?
+    })?;
+
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let member = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))
+        .is_some_and(|ids| ids.split_whitespace().any(|id| id == gid));
+    Some(member)
+}
+
+fn check_udev_rule() -> Check {
+    const NAME: &str = "udev rule";
+
+    match UDEV_RULE_PATHS.iter().find(|p| Path::new(p).exists()) {
+        Some(path) => Check::pass(NAME, *path),
+        None => Check::warn(
+            NAME,
+            "50-uinput.rules not installed",
+            "sudo cp data/50-uinput.rules /etc/udev/rules.d/ && sudo udevadm control --reload-rules",
+        ),
+    }
+}
+
+/// Checks that need the tablet, each skipped once an earlier one fails.
+fn check_tablet(cli: &Cli, config: &Config) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let remaining = |checks: &mut Vec<Check>, names: &[&'static str]| {
+        checks.extend(names.iter().map(|n| Check::skip(n)));
+    };
+    const LATER: [&str; 7] = [
+        "SSH auth",
+        "model detection",
+        "arch detection",
+        "grab helper",
+        "pen device",
+        "touch device",
+        "watchdog file",
+    ];
+
+    if let Err(e) = ssh::check_reachable(&config.host) {
+        checks.push(Check::fail(
+            "SSH reachable",
+            format!("{}:22: {}", config.host, e),
+            "Connect the tablet over USB (10.11.99.1) or set --host to its Wi-Fi address",
+        ));
+        remaining(&mut checks, &LATER);
+        return checks;
+    }
+    checks.push(Check::pass("SSH reachable", format!("{}:22", config.host)));
+
+    let session = match ssh::connect_for_detection(config) {
+        Ok(session) => session,
+        Err(e) => {
+            let fix = match config.auth() {
+                Auth::Key(path) => format!(
+                    "Check the key at {}; copy it with: ssh-copy-id -i {} root@{}",
+                    path.display(),
+                    path.display(),
+                    config.host
+                ),
+                Auth::Password(_) => "Check the password shown in the tablet's Settings > Help > Copyrights and licenses".into(),
+            };
+            checks.push(Check::fail("SSH auth", e.to_string(), fix));
+            remaining(&mut checks, &LATER[1..]);
+            return checks;
+        }
+    };
+    checks.push(Check::pass("SSH auth", "Authenticated as root"));
+
+    let device = match DeviceProfile::detect_via_ssh(&session) {
+        Ok(device) => device,
+        Err(e) => {
+            checks.push(Check::fail("model detection", e.to_string(), "Only the reMarkable 2 and Paper Pro are supported"));
+            remaining(&mut checks, &LATER[2..]);
+            return checks;
+        }
+    };
+    checks.push(Check::pass("model detection", device.name));
+
+    // Device paths depend on the detected model
+    let config = cli.load_config(device);
+
+    match grab::detect_arch(&session) {
+        Ok(arch) => {
+            checks.push(Check::pass("arch detection", arch.to_string()));
+            checks.push(match grab::check_remote_binary_hash(&session, arch) {
+                Ok(true) => Check::pass("grab helper", "Installed and up to date"),
+                Ok(false) => Check::warn(
+                    "grab helper",
+                    "Missing or outdated",
+                    "Uploaded automatically when rm-pad starts with grab_input",
+                ),
+                Err(e) => Check::fail("grab helper", e.to_string(), "Check free space in /tmp on the tablet"),
+            });
+        }
+        Err(e) => {
+            checks.push(Check::fail("arch detection", e.to_string(), "Unsupported tablet architecture"));
+            checks.push(Check::skip("grab helper"));
+        }
+    }
+
+    checks.push(check_remote_device(&session, "pen device", &config.pen_device, "--pen-device"));
+    checks.push(check_remote_device(&session, "touch device", &config.touch_device, "--touch-device"));
+    checks.push(check_remote(
+        &session,
+        "watchdog file",
+        &format!("touch {}", ssh::WATCHDOG_FILE),
+        ssh::WATCHDOG_FILE,
+        "Check that /tmp on the tablet is writable and not full (df -h /tmp)",
+    ));
+
+    checks
+}
+
+fn check_remote_device(session: &Session, name: &'static str, path: &str, flag: &str) -> Check {
+    check_remote(
+        session,
+        name,
+        &format!("test -r {}", path),
+        path,
+        &format!("List devices with: ssh root@<tablet> cat /proc/bus/input/devices, then set {}", flag),
+    )
+}
+
+/// Pass if `command` exits with status 0 on the tablet.
+fn check_remote(session: &Session, name: &'static str, command: &str, detail: &str, fix: &str) -> Check {
+    match ssh::exec(session, command) {
+        Ok((_, 0)) => Check::pass(name, detail),
+        Ok((_, status)) => Check::fail(name, format!("{} (exit status {})", detail, status), fix),
+        Err(e) => Check::fail(name, e.to_string(), fix),
+    }
+}
+
+fn print_table(checks: &[Check]) {
+    let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    let mut fixes = Vec::new();
+
+    for check in checks {
+        let (label, detail) = match &check.outcome {
+            Outcome::Pass(detail) => ("ok", detail.as_str()),
+            Outcome::Warn(detail, fix) => {
+                fixes.push((check.name, fix));
+                ("warn", detail.as_str())
+            }
+            Outcome::Fail(detail, fix) => {
+                fixes.push((check.name, fix));
+                ("FAIL", detail.as_str())
+            }
+            Outcome::Skip => ("skip", "Needs the checks above"),
+        };
+        println!("{:<width$}  {:<4}  {}", check.name, label, detail, width = width);
+    }
+
+    if !fixes.is_empty() {
+        println!("\nSuggested fixes:");
+        for (name, fix) in fixes {
+            println!("  {}: {}", name, fix);
+        }
+    }
+}
@@ -1,76 +1,543 @@
-use std::io::Read;
+//! `rm-pad dump`: print the raw tablet input streams for debugging.
 
-use crate::config::Config;
-use crate::device::DeviceProfile;
-use crate::input::parse_input_event;
-use crate::ssh;
+use std::fmt;
+use std::io::{BufRead, Read};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
-pub fn run_touch(
-    config: &Config,
-    device: &DeviceProfile,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    run_dump(config, device.input_event_size, &config.touch_device, "touch")
+use evdevil::event::{Abs, EventType, Key, Misc, Rel, Switch, Syn};
+use serde::Serialize;
+
+use rm_pad::config::Config;
+use rm_pad::control::{self, Request};
+use rm_pad::device::DeviceProfile;
+use rm_pad::input::{parse_event_time, parse_input_event};
+use rm_pad::ssh;
+use rm_pad::state::{InputKind, OutputFrame};
+use rm_pad::transform::CoordinateTransform;
+
+const EV_SYN: u16 = 0x00;
+const EV_ABS: u16 = 0x03;
+
+/// Slots tracked when decoding touch positions.
+const DECODE_SLOTS: usize = 16;
+
+/// Which input stream(s) to dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpSource {
+    Pen,
+    Touch,
+    Both,
 }
 
-pub fn run_pen(
-    config: &Config,
-    device: &DeviceProfile,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    run_dump(config, device.input_event_size, &config.pen_device, "pen")
+impl fmt::Display for DumpSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DumpSource::Pen => write!(f, "pen"),
+            DumpSource::Touch => write!(f, "touch"),
+            DumpSource::Both => write!(f, "both"),
+        }
+    }
+}
+
+impl FromStr for DumpSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pen" => Ok(DumpSource::Pen),
+            "touch" => Ok(DumpSource::Touch),
+            "both" => Ok(DumpSource::Both),
+            _ => Err(format!("Invalid dump device '{}'. Valid values: pen, touch, both", s)),
+        }
+    }
+}
+
+impl DumpSource {
+    fn inputs(self) -> &'static [InputKind] {
+        match self {
+            DumpSource::Pen => &[InputKind::Pen],
+            DumpSource::Touch => &[InputKind::Touch],
+            DumpSource::Both => &[InputKind::Pen, InputKind::Touch],
+        }
+    }
+}
+
+/// Output format of `rm-pad dump`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpFormat {
+    /// Aligned columns for reading.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+    Csv,
+    /// The format printed by `evtest`.
+    Evtest,
+}
+
+impl fmt::Display for DumpFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DumpFormat::Text => write!(f, "text"),
+            DumpFormat::Json => write!(f, "json"),
+            DumpFormat::Csv => write!(f, "csv"),
+            DumpFormat::Evtest => write!(f, "evtest"),
+        }
+    }
+}
+
+impl FromStr for DumpFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(DumpFormat::Text),
+            "json" => Ok(DumpFormat::Json),
+            "csv" => Ok(DumpFormat::Csv),
+            "evtest" => Ok(DumpFormat::Evtest),
+            _ => Err(format!("Invalid format '{}'. Valid values: text, json, csv, evtest", s)),
+        }
+    }
+}
+
+pub struct DumpOptions {
+    pub source: DumpSource,
+    pub format: DumpFormat,
+    /// Print one entry per `SYN_REPORT` frame instead of one per event.
+    pub frames: bool,
+    /// Show positions and tilt after the configured orientation or transform.
+    pub decoded: bool,
+}
+
+/// One event as read from the tablet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DumpEvent {
+    /// Tablet timestamp, since the Unix epoch.
+    time: Duration,
+    ty: u16,
+    code: u16,
+    value: i32,
 }
 
-fn run_dump(
+impl DumpEvent {
+    fn is_syn_report(&self) -> bool {
+        self.ty == EV_SYN && self.code == Syn::REPORT.raw()
+    }
+}
+
+/// Events up to and including a `SYN_REPORT`.
+struct Frame {
+    input: InputKind,
+    events: Vec<DumpEvent>,
+}
+
+pub fn run(
     config: &Config,
-    input_event_size: usize,
-    device: &str,
-    name: &str,
+    device: &'static DeviceProfile,
+    options: &DumpOptions,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (_cleanup, mut channel) = ssh::open_input_stream(device, config, false)?;
+    if options.frames && options.format == DumpFormat::Evtest {
+        return Err("--frames can't be combined with --format evtest, which already separates frames".into());
+    }
+
+    let inputs = options.source.inputs();
+
+    let (sender, receiver) = mpsc::channel();
+    for &input in inputs {
+        let path = match input {
+            InputKind::Pen => &config.pen_device,
+            InputKind::Touch => &config.touch_device,
+        };
+        let (cleanup, channel) = ssh::open_input_stream(path, config, false)?;
+        eprintln!("Dumping {} events from {}", input, path);
 
-    eprintln!("Dumping {} events from {} (Ctrl+C to stop)\n", name, device);
+        let decoder = options.decoded.then(|| Decoder::new(input, config, device));
+        let sender = sender.clone();
+        thread::spawn(move || {
+            let _cleanup = cleanup;
+            if let Err(e) = read_frames(channel, input, device.input_event_size, decoder, &sender) {
+                let _ = sender.send(Err(format!("{} stream: {}", input, e)));
+            }
+        });
+    }
+    drop(sender);
+    eprintln!("(Ctrl+C to stop)\n");
+
+    let mut printer = Printer::new(options, inputs.len() > 1);
+    for message in receiver {
+        printer.print(&message?)?;
+    }
+    Ok(())
+}
+
+/// Print the frames a running instance writes to its virtual devices.
+pub fn run_output(options: &DumpOptions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if options.decoded {
+        return Err("--decoded has no effect with --output, which already shows the emitted values".into());
+    }
+    if options.frames && options.format == DumpFormat::Evtest {
+        return Err("--frames can't be combined with --format evtest, which already separates frames".into());
+    }
 
+    let inputs = options.source.inputs();
+    let (_, reader) = control::send_request(&Request::WatchOutput { inputs: inputs.to_vec() })?;
+    eprintln!("Dumping {} output of the running instance (Ctrl+C to stop)\n", options.source);
+
+    let mut printer = Printer::new(options, inputs.len() > 1);
+    for line in reader.lines() {
+        let output: OutputFrame = serde_json::from_str(&line?)?;
+        let time = Duration::from_micros(output.time_us as u64);
+        let syn_report = (EV_SYN, Syn::REPORT.raw(), 0);
+        let events = output
+            .events
+            .into_iter()
+            .chain(std::iter::once(syn_report))
+            .map(|(ty, code, value)| DumpEvent { time, ty, code, value })
+            .collect();
+        printer.print(&Frame { input: output.input, events })?;
+    }
+    Err("rm-pad stopped".into())
+}
+
+/// Read events into frames and send them until the stream fails.
+fn read_frames(
+    mut channel: impl Read,
+    input: InputKind,
+    input_event_size: usize,
+    mut decoder: Option<Decoder>,
+    sender: &mpsc::Sender<Result<Frame, String>>,
+) -> std::io::Result<()> {
     let mut buf = vec![0u8; input_event_size];
-    let mut count: u64 = 0;
+    let mut events = Vec::new();
 
     loop {
         channel.read_exact(&mut buf)?;
+        let (Some(ev), Some(time)) = (parse_input_event(&buf), parse_event_time(&buf)) else {
+            continue;
+        };
 
-        let Some(ev) = parse_input_event(&buf) else {
+        let event = DumpEvent { time, ty: ev.event_type().raw(), code: ev.raw_code(), value: ev.raw_value() };
+        events.push(event);
+        if !event.is_syn_report() {
             continue;
+        }
+
+        if let Some(decoder) = decoder.as_mut() {
+            events = decoder.decode(&events);
+        }
+        if sender.send(Ok(Frame { input, events: std::mem::take(&mut events) })).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Name of an event type, e.g. `EV_ABS`.
+fn type_name(ty: u16) -> String {
+    format!("{:?}", EventType::from_raw(ty))
+}
+
+/// Name of an event code, e.g. `ABS_MT_POSITION_X` or `BTN_TOUCH`.
+fn code_name(ty: u16, code: u16) -> String {
+    match EventType::from_raw(ty) {
+        EventType::SYN => format!("{:?}", Syn::from_raw(code)),
+        EventType::KEY => format!("{:?}", Key::from_raw(code)),
+        EventType::REL => format!("{:?}", Rel::from_raw(code)),
+        EventType::ABS => format!("{:?}", Abs::from_raw(code)),
+        EventType::MSC => format!("{:?}", Misc::from_raw(code)),
+        EventType::SW => format!("{:?}", Switch::from_raw(code)),
+        _ => format!("{:#x}", code),
+    }
+}
+
+fn format_time(time: Duration) -> String {
+    format!("{}.{:06}", time.as_secs(), time.subsec_micros())
+}
+
+#[derive(Serialize)]
+struct JsonEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input: Option<InputKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_us: Option<u128>,
+    #[serde(rename = "type")]
+    ty: String,
+    code: String,
+    value: i32,
+}
+
+#[derive(Serialize)]
+struct JsonFrame {
+    frame: u64,
+    input: InputKind,
+    time_us: u128,
+    events: Vec<JsonEvent>,
+}
+
+impl JsonEvent {
+    fn new(event: &DumpEvent, input: Option<InputKind>) -> Self {
+        Self {
+            input,
+            time_us: input.map(|_| event.time.as_micros()),
+            ty: type_name(event.ty),
+            code: code_name(event.ty, event.code),
+            value: event.value,
+        }
+    }
+}
+
+struct Printer<'a> {
+    options: &'a DumpOptions,
+    /// Tag lines with the input when dumping both.
+    tag_input: bool,
+    count: u64,
+}
+
+impl<'a> Printer<'a> {
+    fn new(options: &'a DumpOptions, tag_input: bool) -> Self {
+        if options.format == DumpFormat::Csv {
+            let frame = if options.frames { "frame," } else { "" };
+            println!("{}input,time,type,code,value", frame);
+        }
+        Self { options, tag_input, count: 0 }
+    }
+
+    fn print(&mut self, frame: &Frame) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.options.frames {
+            self.count += 1;
+            return self.print_frame(frame);
+        }
+        for event in &frame.events {
+            self.count += 1;
+            self.print_event(frame.input, event)?;
+        }
+        Ok(())
+    }
+
+    fn print_event(&self, input: InputKind, event: &DumpEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let tag = if self.tag_input { format!("[{}] ", input) } else { String::new() };
+        match self.options.format {
+            DumpFormat::Text => println!(
+                "{:6}  {}  {}{:<20}  value={}",
+                self.count,
+                format_time(event.time),
+                tag,
+                code_name(event.ty, event.code),
+                event.value
+            ),
+            DumpFormat::Json => println!("{}", serde_json::to_string(&JsonEvent::new(event, Some(input)))?),
+            DumpFormat::Csv => println!(
+                "{},{},{},{},{}",
+                input,
+                format_time(event.time),
+                type_name(event.ty),
+                code_name(event.ty, event.code),
+                event.value
+            ),
+            DumpFormat::Evtest if event.is_syn_report() => println!(
+                "{}Event: time {}, -------------- SYN_REPORT ------------",
+                tag,
+                format_time(event.time)
+            ),
+            DumpFormat::Evtest => println!(
+                "{}Event: time {}, type {} ({}), code {} ({}), value {}",
+                tag,
+                format_time(event.time),
+                event.ty,
+                type_name(event.ty),
+                event.code,
+                code_name(event.ty, event.code),
+                event.value
+            ),
+        }
+        Ok(())
+    }
+
+    fn print_frame(&self, frame: &Frame) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(report) = frame.events.last() else { return Ok(()) };
+        let events = frame.events.iter().filter(|e| !e.is_syn_report());
+
+        match self.options.format {
+            DumpFormat::Text => {
+                let values: Vec<String> = events
+                    .map(|e| format!("{}={}", code_name(e.ty, e.code), e.value))
+                    .collect();
+                println!(
+                    "{:6}  {}  {:<5}  {}",
+                    self.count,
+                    format_time(report.time),
+                    frame.input,
+                    values.join(" ")
+                );
+            }
+            DumpFormat::Json => {
+                let json = JsonFrame {
+                    frame: self.count,
+                    input: frame.input,
+                    time_us: report.time.as_micros(),
+                    events: events.map(|e| JsonEvent::new(e, None)).collect(),
+                };
+                println!("{}", serde_json::to_string(&json)?);
+            }
+            DumpFormat::Csv => {
+                for event in events {
+                    println!(
+                        "{},{},{},{},{},{}",
+                        self.count,
+                        frame.input,
+                        format_time(event.time),
+                        type_name(event.ty),
+                        code_name(event.ty, event.code),
+                        event.value
+                    );
+                }
+            }
+            DumpFormat::Evtest => unreachable!("rejected in run"),
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites positions and tilt in each frame to the values rm-pad emits
+/// after applying the orientation or configured transform.
+struct Decoder {
+    input: InputKind,
+    transform: CoordinateTransform,
+    device: &'static DeviceProfile,
+    slot: usize,
+    /// Last raw position per slot; the pen only uses slot 0.
+    positions: [(i32, i32); DECODE_SLOTS],
+    tilt: (i32, i32),
+}
+
+/// An event in a frame being decoded, or where a transformed pair goes.
+enum Decoded {
+    Event(DumpEvent),
+    Position { slot: usize, time: Duration },
+    Tilt { time: Duration },
+}
+
+impl Decoder {
+    fn new(input: InputKind, config: &Config, device: &'static DeviceProfile) -> Self {
+        let (affine, x_max, y_max) = match input {
+            InputKind::Pen => (config.pen_transform, device.pen_x_max, device.pen_y_max),
+            InputKind::Touch => (config.touch_transform, device.touch_x_max, device.touch_y_max),
+        };
+        Self {
+            input,
+            transform: CoordinateTransform::new(affine, config.orientation, x_max, y_max),
+            device,
+            slot: 0,
+            positions: [(0, 0); DECODE_SLOTS],
+            tilt: (0, 0),
+        }
+    }
+
+    fn decode(&mut self, events: &[DumpEvent]) -> Vec<DumpEvent> {
+        let (x_code, y_code) = match self.input {
+            InputKind::Pen => (Abs::X.raw(), Abs::Y.raw()),
+            InputKind::Touch => (Abs::MT_POSITION_X.raw(), Abs::MT_POSITION_Y.raw()),
+        };
+        let mut decoded = Vec::with_capacity(events.len());
+
+        for &event in events {
+            if event.ty != EV_ABS {
+                decoded.push(Decoded::Event(event));
+                continue;
+            }
+            let slot = self.slot;
+            let code = event.code;
+
+            if code == Abs::MT_SLOT.raw() {
+                self.slot = (event.value.max(0) as usize).min(DECODE_SLOTS - 1);
+                decoded.push(Decoded::Event(event));
+            } else if code == x_code || code == y_code {
+                let position = &mut self.positions[slot];
+                if code == x_code { position.0 = event.value } else { position.1 = event.value }
+                // Both axes go where the first one was, since a rotation swaps them
+                if !decoded.iter().any(|d| matches!(d, Decoded::Position { slot: s, .. } if *s == slot)) {
+                    decoded.push(Decoded::Position { slot, time: event.time });
+                }
+            } else if self.input == InputKind::Pen && (code == Abs::TILT_X.raw() || code == Abs::TILT_Y.raw()) {
+                if code == Abs::TILT_X.raw() { self.tilt.0 = event.value } else { self.tilt.1 = event.value }
+                if !decoded.iter().any(|d| matches!(d, Decoded::Tilt { .. })) {
+                    decoded.push(Decoded::Tilt { time: event.time });
+                }
+            } else {
+                decoded.push(Decoded::Event(event));
+            }
+        }
+
+        decoded.into_iter().flat_map(|d| self.resolve(d, x_code, y_code)).collect()
+    }
+
+    fn resolve(&self, decoded: Decoded, x_code: u16, y_code: u16) -> Vec<DumpEvent> {
+        let device = self.device;
+        let ((x, y), time) = match decoded {
+            Decoded::Event(event) => return vec![event],
+            Decoded::Position { slot, time } => {
+                let (x, y) = self.positions[slot];
+                let position = match self.input {
+                    InputKind::Pen => self.transform.transform_pen(x, y, device.pen_x_max, device.pen_y_max),
+                    InputKind::Touch => self.transform.transform_touch(
+                        x.clamp(0, device.touch_x_max),
+                        y.clamp(0, device.touch_y_max),
+                        device.touch_x_max,
+                        device.touch_y_max,
+                    ),
+                };
+                (position, time)
+            }
+            Decoded::Tilt { time } => {
+                let (tilt_x, tilt_y) =
+                    self.transform.transform_tilt(self.tilt.0, self.tilt.1, device.pen_tilt_range);
+                return vec![
+                    DumpEvent { time, ty: EV_ABS, code: Abs::TILT_X.raw(), value: tilt_x },
+                    DumpEvent { time, ty: EV_ABS, code: Abs::TILT_Y.raw(), value: tilt_y },
+                ];
+            }
         };
+        vec![
+            DumpEvent { time, ty: EV_ABS, code: x_code, value: x },
+            DumpEvent { time, ty: EV_ABS, code: y_code, value: y },
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rm_pad::device::RM2;
+    use rm_pad::orientation::Orientation;
+
+    #[test]
+    fn test_code_names() {
+        assert_eq!(code_name(EV_ABS, 0x35), "ABS_MT_POSITION_X");
+        assert_eq!(code_name(0x01, 0x14a), "BTN_TOUCH");
+        assert_eq!(code_name(EV_SYN, 0), "SYN_REPORT");
+        assert_eq!(type_name(EV_ABS), "EV_ABS");
+    }
+
+    #[test]
+    fn test_decode_swaps_axes() {
+        let event = |code: Abs, value| DumpEvent { time: Duration::ZERO, ty: EV_ABS, code: code.raw(), value };
+        let mut decoder = Decoder {
+            input: InputKind::Touch,
+            transform: CoordinateTransform::Orientation(Orientation::LandscapeRight),
+            device: &RM2,
+            slot: 0,
+            positions: [(0, 0); DECODE_SLOTS],
+            tilt: (0, 0),
+        };
+
+        // A lone Y update still produces both axes, using the last X
+        decoder.decode(&[event(Abs::MT_POSITION_X, 100), event(Abs::MT_POSITION_Y, 200)]);
+        let decoded = decoder.decode(&[event(Abs::MT_POSITION_Y, 300)]);
+        let expected = Orientation::LandscapeRight.transform_touch(100, 300, RM2.touch_x_max, RM2.touch_y_max);
 
-        count += 1;
-        let name = format_event_code(ev.event_type().raw(), ev.raw_code());
-        println!("{:6}  {}  value={}", count, name, ev.raw_value());
-    }
-}
-
-fn format_event_code(ty: u16, code: u16) -> String {
-    match ty {
-        0 => "SYN_REPORT".into(),
-        1 => format!("KEY/{}", code),
-        3 => format!("ABS_{}", abs_code_name(code)),
-        _ => format!("type{}/code{}", ty, code),
-    }
-}
-
-fn abs_code_name(code: u16) -> &'static str {
-    match code {
-        0x00 => "X",
-        0x01 => "Y",
-        0x18 => "PRESSURE",
-        0x19 => "DISTANCE",
-        0x1a => "TILT_X",
-        0x1b => "TILT_Y",
-        0x2f => "MT_SLOT",
-        0x30 => "MT_TOUCH_MAJOR",
-        0x31 => "MT_TOUCH_MINOR",
-        0x34 => "MT_ORIENTATION",
-        0x35 => "MT_POSITION_X",
-        0x36 => "MT_POSITION_Y",
-        0x37 => "MT_TOOL_TYPE",
-        0x39 => "MT_TRACKING_ID",
-        0x3a => "MT_PRESSURE",
-        _ => "?",
+        assert_eq!(decoded.len(), 2);
+        assert_eq!((decoded[0].value, decoded[1].value), expected);
     }
 }
@@ -0,0 +1,115 @@
+//! Errors returned by the library.
+
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing a stream, virtual device or file failed.
+    Io(io::Error),
+    /// The SSH connection to the tablet failed.
+    Ssh(ssh2::Error),
+    /// The host name didn't resolve to an address.
+    HostNotFound(String),
+    /// The tablet rejected the key or password.
+    AuthenticationFailed,
+    /// The tablet model isn't one rm-pad knows.
+    UnsupportedModel(String),
+    /// The tablet CPU isn't one the grab helper is built for.
+    UnsupportedArch(String),
+    /// A command on the tablet exited with a non-zero status.
+    RemoteCommand { action: &'static str, status: i32 },
+    /// The grab helper is still running, e.g. from another instance.
+    GrabHelperRunning,
+    /// The configuration contradicts itself.
+    InvalidConfig(&'static str),
+    /// A recording couldn't be read or doesn't match the device.
+    InvalidRecording(String),
+    /// The control socket couldn't be reached or refused a request.
+    Control(String),
+    /// A recording or control message isn't valid JSON.
+    Json(serde_json::Error),
+    /// The pen or touch thread panicked.
+    ThreadPanicked(String),
+}
+
+impl Error {
+    /// Whether this just means the input stream ran out, e.g. at the end
+    /// of a replay.
+    pub fn is_end_of_stream(&self) -> bool {
+        matches!(self, Error::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof)
+    }
+
+    /// Whether the tablet couldn't be reached at all, e.g. because it isn't
+    /// plugged in.
+    pub fn is_unreachable(&self) -> bool {
+        match self {
+            Error::HostNotFound(_) => true,
+            Error::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::HostUnreachable
+                    | io::ErrorKind::NetworkUnreachable
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Ssh(e) => write!(f, "SSH: {}", e),
+            Error::HostNotFound(host) => write!(f, "Could not resolve host address: {}", host),
+            Error::AuthenticationFailed => write!(f, "SSH authentication failed"),
+            Error::UnsupportedModel(model) if model.is_empty() => write!(f, "Device model is empty"),
+            Error::UnsupportedModel(model) => write!(f, "Unsupported device model: '{}'", model),
+            Error::UnsupportedArch(arch) => write!(f, "Unsupported tablet architecture: {}", arch),
+            Error::RemoteCommand { action, status } => {
+                write!(f, "Failed to {} (exit status {})", action, status)
+            }
+            Error::GrabHelperRunning => {
+                write!(f, "The grab helper is running on the tablet; stop rm-pad first")
+            }
+            Error::InvalidConfig(message) => write!(f, "{}", message),
+            Error::InvalidRecording(message) => write!(f, "Invalid recording: {}", message),
+            Error::Control(message) => write!(f, "{}", message),
+            Error::Json(e) => write!(f, "{}", e),
+            Error::ThreadPanicked(kind) => write!(f, "The {} thread panicked", kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Ssh(e) => Some(e),
+            Error::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<ssh2::Error> for Error {
+    fn from(e: ssh2::Error) -> Self {
+        Error::Ssh(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
@@ -14,11 +14,23 @@ use std::io::{Read, Write};
 use sha2::{Digest, Sha256};
 use ssh2::Session;
 
+use crate::error::{Error, Result};
+use crate::ssh::{exec, WATCHDOG_FILE};
+
 const GRAB_ARMV7: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/evgrab-armv7"));
 const GRAB_AARCH64: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/evgrab-aarch64"));
 
 const REMOTE_PATH: &str = "/tmp/rm-pad-grab";
 
+/// Helper stderr log. `/tmp` on the tablet is a small tmpfs, so it is
+/// rotated once it exceeds `LOG_MAX_BYTES`, keeping one old copy.
+const LOG_PATH: &str = "/tmp/rm-pad-grab.log";
+const LOG_MAX_BYTES: u64 = 64 * 1024;
+
+/// Upload temp files older than this many minutes were left by an
+/// interrupted upload.
+const STALE_UPLOAD_MINUTES: u32 = 1;
+
 #[derive(Debug, Clone, Copy)]
 pub enum Arch {
     Armv7,
@@ -35,7 +47,7 @@ impl fmt::Display for Arch {
 }
 
 /// Detect the tablet's CPU architecture by running `uname -m` over SSH.
-pub fn detect_arch(session: &Session) -> Result<Arch, Box<dyn std::error::Error + Send + Sync>> {
+pub fn detect_arch(session: &Session) -> Result<Arch> {
     let mut channel = session.channel_session()?;
     channel.exec("uname -m")?;
 
@@ -50,7 +62,7 @@ pub fn detect_arch(session: &Session) -> Result<Arch, Box<dyn std::error::Error
     match output.trim() {
         "armv7l" => Ok(Arch::Armv7),
         "aarch64" => Ok(Arch::Aarch64),
-        other => Err(format!("Unsupported tablet architecture: {}", other).into()),
+        other => Err(Error::UnsupportedArch(other.to_string())),
     }
 }
 
@@ -67,10 +79,10 @@ fn compute_binary_hash(arch: Arch) -> String {
 
 /// Check if the remote binary exists and matches our embedded binary hash.
 /// Returns Ok(true) if hash matches, Ok(false) if file doesn't exist or hash doesn't match.
-fn check_remote_binary_hash(
+pub fn check_remote_binary_hash(
     session: &Session,
     arch: Arch,
-) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<bool> {
     let expected_hash = compute_binary_hash(arch);
     
     let mut channel = session.channel_session()?;
@@ -105,7 +117,7 @@ fn check_remote_binary_hash(
 /// Remove the remote binary if it exists.
 fn remove_remote_binary(
     session: &Session,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<()> {
     let mut channel = session.channel_session()?;
     channel.exec(&format!("rm -f {}", REMOTE_PATH))?;
 
@@ -114,7 +126,7 @@ fn remove_remote_binary(
 
     let status = channel.exit_status()?;
     if status != 0 {
-        return Err(format!("Failed to remove remote binary (exit status {})", status).into());
+        return Err(Error::RemoteCommand { action: "remove remote binary", status });
     }
 
     log::debug!("Removed remote binary");
@@ -129,7 +141,7 @@ fn remove_remote_binary(
 pub fn upload_helper(
     session: &Session,
     arch: Arch,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<()> {
     let binary = match arch {
         Arch::Armv7 => GRAB_ARMV7,
         Arch::Aarch64 => GRAB_AARCH64,
@@ -158,7 +170,7 @@ pub fn upload_helper(
 
     let status = channel.exit_status()?;
     if status != 0 {
-        return Err(format!("Failed to upload grab helper (exit status {})", status).into());
+        return Err(Error::RemoteCommand { action: "upload grab helper", status });
     }
 
     log::info!("Grab helper uploaded successfully");
@@ -170,7 +182,7 @@ pub fn upload_helper(
 pub fn ensure_binary_valid(
     session: &Session,
     arch: Arch,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<()> {
     match check_remote_binary_hash(session, arch)? {
         true => {
             log::debug!("Using existing remote binary (hash verified)");
@@ -184,6 +196,83 @@ pub fn ensure_binary_valid(
     }
 }
 
+/// Rotate the helper log and remove temp files from interrupted uploads.
+/// Runs before every grab.
+pub fn tidy(session: &Session) -> Result<()> {
+    let command = format!(
+        "if [ \"$(stat -c %s {log} 2>/dev/null || echo 0)\" -gt {max} ]; then mv -f {log} {log}.1; fi; \
+         find /tmp -maxdepth 1 -name '{name}.[0-9]*' -mmin +{stale} -exec rm -f {{}} + 2>/dev/null; true",
+        log = LOG_PATH,
+        max = LOG_MAX_BYTES,
+        name = REMOTE_PATH.trim_start_matches("/tmp/"),
+        stale = STALE_UPLOAD_MINUTES,
+    );
+    exec(session, &command)?;
+    Ok(())
+}
+
+/// Shell command printing the helper log: the whole log including the
+/// rotated copy, or when following, the last `lines` lines and then
+/// anything appended (surviving rotation).
+pub fn log_command(follow: bool, lines: usize) -> String {
+    if follow {
+        format!("tail -n {} -F {} 2>/dev/null", lines, LOG_PATH)
+    } else {
+        format!("cat {log}.1 {log} 2>/dev/null; true", log = LOG_PATH)
+    }
+}
+
+/// Shell command printing the size of the helper log, 0 if there is none.
+pub fn log_size_command() -> String {
+    format!("stat -c %s {} 2>/dev/null || echo 0", LOG_PATH)
+}
+
+/// Shell command following the helper log from byte `offset` on.
+pub fn follow_log_command(offset: u64) -> String {
+    format!("tail -c +{} -F {} 2>/dev/null", offset + 1, LOG_PATH)
+}
+
+/// Level to show a line of the helper log at: failures such as
+/// `evgrab: grab /dev/input/event1: Device or resource busy` or
+/// `evgrab: watchdog stale, exiting` are warnings, routine messages such as
+/// `evgrab: grabbed /dev/input/event1` are info.
+pub fn log_level(line: &str) -> log::Level {
+    let message = line.strip_prefix("evgrab: ").unwrap_or(line);
+    if message.contains(": ") || message.starts_with("watchdog stale") {
+        log::Level::Warn
+    } else {
+        log::Level::Info
+    }
+}
+
+/// Shell command removing everything rm-pad leaves on the tablet: the
+/// helper, upload temp files, logs and the watchdog file.
+pub fn clean_command() -> String {
+    format!(
+        "rm -f {path} {path}.[0-9]* {log} {log}.1 {watchdog}",
+        path = REMOTE_PATH,
+        log = LOG_PATH,
+        watchdog = WATCHDOG_FILE,
+    )
+}
+
+/// Remove rm-pad's files from the tablet. Refuses while a grab helper is
+/// running, since removing the watchdog file would end its grab.
+pub fn clean(session: &Session) -> Result<()> {
+    let (_, status) = exec(session, "pidof rm-pad-grab")?;
+    if status == 0 {
+        return Err(Error::GrabHelperRunning);
+    }
+
+    let (_, status) = exec(session, &clean_command())?;
+    if status != 0 {
+        return Err(Error::RemoteCommand { action: "clean up the tablet", status });
+    }
+
+    log::info!("Removed rm-pad files from the tablet");
+    Ok(())
+}
+
 /// Build the remote command that grabs a device and streams events.
 ///
 /// Stderr is redirected to a log file on the tablet for diagnostics.
@@ -191,7 +280,7 @@ pub fn ensure_binary_valid(
 /// delivery (on SSH disconnect) goes directly to the right process.
 pub fn grab_command(device_path: &str) -> String {
     format!(
-        "exec {} {} 2>>{}.log",
-        REMOTE_PATH, device_path, REMOTE_PATH
+        "exec {} {} 2>>{}",
+        REMOTE_PATH, device_path, LOG_PATH
     )
 }
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use evdevil::event::{EventType, InputEvent};
 
 pub const INPUT_EVENT_SIZE_32: usize = 16;
@@ -9,10 +11,16 @@ pub const EV_ABS: u16 = 0x03;
 pub const SYN_REPORT: u16 = 0;
 
 pub const ABS_MT_SLOT: u16 = 0x2f;
+pub const ABS_MT_TOUCH_MAJOR: u16 = 0x30;
+pub const ABS_MT_TOUCH_MINOR: u16 = 0x31;
+pub const ABS_MT_ORIENTATION: u16 = 0x34;
 pub const ABS_MT_POSITION_X: u16 = 0x35;
 pub const ABS_MT_POSITION_Y: u16 = 0x36;
+pub const ABS_MT_TOOL_TYPE: u16 = 0x37;
 pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+pub const ABS_MT_PRESSURE: u16 = 0x3a;
 pub const ABS_PRESSURE: u16 = 0x18;
+pub const ABS_DISTANCE: u16 = 0x19;
 
 /// Parse a Linux input_event from raw bytes (32-bit or 64-bit format).
 pub fn parse_input_event(buf: &[u8]) -> Option<InputEvent> {
@@ -25,6 +33,22 @@ pub fn parse_input_event(buf: &[u8]) -> Option<InputEvent> {
     }
 }
 
+/// Parse the timestamp of a raw input_event, as time since the Unix epoch.
+pub fn parse_event_time(buf: &[u8]) -> Option<Duration> {
+    let (sec, usec) = match buf.len() {
+        len if len >= INPUT_EVENT_SIZE_64 => (
+            i64::from_le_bytes(buf[0..8].try_into().ok()?),
+            i64::from_le_bytes(buf[8..16].try_into().ok()?),
+        ),
+        len if len >= INPUT_EVENT_SIZE_32 => (
+            i32::from_le_bytes(buf[0..4].try_into().ok()?) as i64,
+            i32::from_le_bytes(buf[4..8].try_into().ok()?) as i64,
+        ),
+        _ => return None,
+    };
+    Some(Duration::new(u64::try_from(sec).ok()?, u32::try_from(usec * 1000).ok()?))
+}
+
 fn parse_input_event_32(buf: &[u8]) -> Option<InputEvent> {
     let ty = u16::from_le_bytes([buf[8], buf[9]]);
     let code = u16::from_le_bytes([buf[10], buf[11]]);
@@ -0,0 +1,456 @@
+//! In-process gesture recognition for hosts without libinput gestures.
+//!
+//! The recogniser looks at the active contacts of each touch frame (in
+//! output coordinates) and turns them into clicks, scrolling, pinch-zoom and
+//! swipe shortcuts, which are written to a separate virtual keyboard/mouse.
+
+use std::time::{Duration, Instant};
+
+use evdevil::event::{InputEvent, Key, KeyEvent, KeyState, Rel, RelEvent};
+use evdevil::uinput::UinputDevice;
+use serde::Deserialize;
+
+use crate::chord::KeyChord;
+use crate::error::Result;
+
+use super::keyboard::{create_keyboard_device, tap_chord};
+use super::GESTURE_PRODUCT_ID;
+
+/// Name of the virtual gesture device.
+const GESTURE_DEVICE_NAME: &str = "reMarkable Gestures";
+
+/// Longest contact that still counts as a tap.
+const TAP_TIMEOUT: Duration = Duration::from_millis(200);
+/// Movement (mm) after which a contact no longer counts as a tap.
+const TAP_MOVE_MM: f64 = 3.0;
+/// Two-finger centroid movement (mm) that starts a scroll.
+const SCROLL_START_MM: f64 = 3.0;
+/// Change in two-finger spread (mm) that starts a pinch.
+const PINCH_START_MM: f64 = 6.0;
+/// Three-finger movement (mm) that triggers a swipe.
+const SWIPE_MM: f64 = 20.0;
+/// High-resolution wheel units per mm of scroll or pinch movement.
+const HI_RES_PER_MM: f64 = 20.0;
+/// High-resolution wheel units per legacy wheel notch.
+const HI_RES_PER_NOTCH: i32 = 120;
+
+/// `[gestures]` section of the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GestureConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Content follows the fingers when scrolling.
+    #[serde(default = "default_true")]
+    pub natural_scroll: bool,
+    pub swipe_left: Option<KeyChord>,
+    pub swipe_right: Option<KeyChord>,
+    pub swipe_up: Option<KeyChord>,
+    pub swipe_down: Option<KeyChord>,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            natural_scroll: true,
+            swipe_left: None,
+            swipe_right: None,
+            swipe_up: None,
+            swipe_down: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A touch contact in output coordinates.
+#[derive(Debug, Clone, Copy)]
+pub struct Contact {
+    pub slot: usize,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwipeDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Output of the recogniser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GestureAction {
+    Click(Key),
+    /// Scroll amount in high-resolution wheel units (positive = up/right).
+    Scroll { vertical: i32, horizontal: i32 },
+    PinchBegin,
+    /// Zoom amount in high-resolution wheel units (positive = zoom in).
+    Pinch(i32),
+    PinchEnd,
+    Swipe(SwipeDirection),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Undecided,
+    Scroll,
+    Pinch,
+    /// Gesture finished for this session (swipe fired, finger count changed).
+    Done,
+}
+
+/// One touch session, from first contact down until all fingers are lifted.
+struct Session {
+    started: Instant,
+    max_contacts: usize,
+    moved: bool,
+    mode: Mode,
+    starts: Vec<Contact>,
+    /// Contact count the reference values below were taken with.
+    count: usize,
+    origin: (f64, f64),
+    last_centroid: (f64, f64),
+    last_spread: f64,
+    remainder: (f64, f64),
+}
+
+impl Session {
+    fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            max_contacts: 0,
+            moved: false,
+            mode: Mode::Undecided,
+            starts: Vec::new(),
+            count: 0,
+            origin: (0.0, 0.0),
+            last_centroid: (0.0, 0.0),
+            last_spread: 0.0,
+            remainder: (0.0, 0.0),
+        }
+    }
+}
+
+/// Gesture state machine, independent of any output device.
+pub struct GestureRecognizer {
+    /// Touch units per millimetre.
+    resolution: f64,
+    natural_scroll: bool,
+    session: Option<Session>,
+}
+
+impl GestureRecognizer {
+    pub fn new(resolution: i32, natural_scroll: bool) -> Self {
+        Self {
+            resolution: resolution.max(1) as f64,
+            natural_scroll,
+            session: None,
+        }
+    }
+
+    /// Drop the current session without emitting anything (e.g. palm rejected).
+    pub fn cancel(&mut self) -> Vec<GestureAction> {
+        match self.session.take() {
+            Some(session) if session.mode == Mode::Pinch => vec![GestureAction::PinchEnd],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Feed the active contacts of one frame.
+    pub fn update(&mut self, contacts: &[Contact], now: Instant) -> Vec<GestureAction> {
+        let mut actions = Vec::new();
+
+        if contacts.is_empty() {
+            if let Some(session) = self.session.take() {
+                self.finish_session(session, now, &mut actions);
+            }
+            return actions;
+        }
+
+        let resolution = self.resolution;
+        let session = self.session.get_or_insert_with(|| Session::new(now));
+        session.max_contacts = session.max_contacts.max(contacts.len());
+
+        for c in contacts {
+            match session.starts.iter().find(|s| s.slot == c.slot) {
+                Some(start) => {
+                    let dist = distance((start.x as f64, start.y as f64), (c.x as f64, c.y as f64));
+                    if dist > TAP_MOVE_MM * resolution {
+                        session.moved = true;
+                    }
+                }
+                None => session.starts.push(*c),
+            }
+        }
+
+        let centroid = centroid(contacts);
+        let spread = spread(contacts);
+
+        if contacts.len() != session.count {
+            if session.count != 0 {
+                // Finger count changed mid-gesture: stop, but keep any tap candidate.
+                if session.mode == Mode::Pinch {
+                    actions.push(GestureAction::PinchEnd);
+                }
+                if session.mode != Mode::Undecided {
+                    session.mode = Mode::Done;
+                }
+            }
+            session.count = contacts.len();
+            session.origin = centroid;
+            session.last_centroid = centroid;
+            session.last_spread = spread;
+        }
+
+        match (contacts.len(), session.mode) {
+            (2, Mode::Undecided) => {
+                if (spread - session.last_spread).abs() > PINCH_START_MM * resolution {
+                    session.mode = Mode::Pinch;
+                    session.last_spread = spread;
+                    actions.push(GestureAction::PinchBegin);
+                } else if distance(session.origin, centroid) > SCROLL_START_MM * resolution {
+                    session.mode = Mode::Scroll;
+                    session.last_centroid = centroid;
+                }
+            }
+            (2, Mode::Scroll) => {
+                let dx = (centroid.0 - session.last_centroid.0) / resolution * HI_RES_PER_MM;
+                let dy = (centroid.1 - session.last_centroid.1) / resolution * HI_RES_PER_MM;
+                session.last_centroid = centroid;
+
+                // Natural scrolling: fingers moving down scroll up (positive wheel).
+                let sign = if self.natural_scroll { 1.0 } else { -1.0 };
+                let (horizontal, vertical) =
+                    take_whole(&mut session.remainder, (-dx * sign, dy * sign));
+                if horizontal != 0 || vertical != 0 {
+                    actions.push(GestureAction::Scroll { vertical, horizontal });
+                }
+            }
+            (2, Mode::Pinch) => {
+                let delta = (spread - session.last_spread) / resolution * HI_RES_PER_MM;
+                session.last_spread = spread;
+
+                let (zoom, _) = take_whole(&mut session.remainder, (delta, 0.0));
+                if zoom != 0 {
+                    actions.push(GestureAction::Pinch(zoom));
+                }
+            }
+            (3, Mode::Undecided) => {
+                let dx = centroid.0 - session.origin.0;
+                let dy = centroid.1 - session.origin.1;
+                if dx.hypot(dy) > SWIPE_MM * resolution {
+                    let direction = if dx.abs() > dy.abs() {
+                        if dx < 0.0 { SwipeDirection::Left } else { SwipeDirection::Right }
+                    } else if dy < 0.0 {
+                        SwipeDirection::Up
+                    } else {
+                        SwipeDirection::Down
+                    };
+                    session.mode = Mode::Done;
+                    actions.push(GestureAction::Swipe(direction));
+                }
+            }
+            _ => {}
+        }
+
+        actions
+    }
+
+    fn finish_session(&self, session: Session, now: Instant, actions: &mut Vec<GestureAction>) {
+        match session.mode {
+            Mode::Pinch => actions.push(GestureAction::PinchEnd),
+            Mode::Undecided if !session.moved && now - session.started <= TAP_TIMEOUT => {
+                let button = match session.max_contacts {
+                    1 => Key::BTN_LEFT,
+                    2 => Key::BTN_RIGHT,
+                    _ => Key::BTN_MIDDLE,
+                };
+                actions.push(GestureAction::Click(button));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn centroid(contacts: &[Contact]) -> (f64, f64) {
+    let n = contacts.len() as f64;
+    let (sx, sy) = contacts
+        .iter()
+        .fold((0.0, 0.0), |(sx, sy), c| (sx + c.x as f64, sy + c.y as f64));
+    (sx / n, sy / n)
+}
+
+/// Mean distance of the contacts from their centroid.
+fn spread(contacts: &[Contact]) -> f64 {
+    let c = centroid(contacts);
+    contacts
+        .iter()
+        .map(|p| distance(c, (p.x as f64, p.y as f64)))
+        .sum::<f64>()
+        / contacts.len() as f64
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+/// Add `delta` to `remainder` and take out the whole units.
+fn take_whole(remainder: &mut (f64, f64), delta: (f64, f64)) -> (i32, i32) {
+    remainder.0 += delta.0;
+    remainder.1 += delta.1;
+    let whole = (remainder.0.trunc(), remainder.1.trunc());
+    remainder.0 -= whole.0;
+    remainder.1 -= whole.1;
+    (whole.0 as i32, whole.1 as i32)
+}
+
+/// Gesture recogniser plus the virtual device it writes to.
+pub struct Gestures {
+    recognizer: GestureRecognizer,
+    config: GestureConfig,
+    uinput: UinputDevice,
+    /// Accumulated high-resolution units not yet sent as legacy notches.
+    wheel_acc: i32,
+    hwheel_acc: i32,
+}
+
+impl Gestures {
+    pub fn new(
+        config: &GestureConfig,
+        resolution: i32,
+    ) -> Result<Self> {
+        let uinput = create_keyboard_device(GESTURE_DEVICE_NAME, GESTURE_PRODUCT_ID)?;
+        Ok(Self {
+            recognizer: GestureRecognizer::new(resolution, config.natural_scroll),
+            config: config.clone(),
+            uinput,
+            wheel_acc: 0,
+            hwheel_acc: 0,
+        })
+    }
+
+    pub fn process(
+        &mut self,
+        contacts: &[Contact],
+    ) -> Result<()> {
+        let actions = self.recognizer.update(contacts, Instant::now());
+        self.emit(&actions)
+    }
+
+    pub fn cancel(&mut self) -> Result<()> {
+        let actions = self.recognizer.cancel();
+        self.emit(&actions)
+    }
+
+    fn emit(&mut self, actions: &[GestureAction]) -> Result<()> {
+        for action in actions {
+            match *action {
+                GestureAction::Click(button) => {
+                    self.uinput.write(&[KeyEvent::new(button, KeyState::PRESSED).into()])?;
+                    self.uinput.write(&[KeyEvent::new(button, KeyState::RELEASED).into()])?;
+                }
+                GestureAction::Scroll { vertical, horizontal } => {
+                    let events = self.wheel_events(vertical, horizontal);
+                    self.uinput.write(&events)?;
+                }
+                GestureAction::PinchBegin => {
+                    self.wheel_acc = 0;
+                    self.uinput.write(&[KeyEvent::new(Key::KEY_LEFTCTRL, KeyState::PRESSED).into()])?;
+                }
+                GestureAction::Pinch(zoom) => {
+                    let events = self.wheel_events(zoom, 0);
+                    self.uinput.write(&events)?;
+                }
+                GestureAction::PinchEnd => {
+                    self.uinput.write(&[KeyEvent::new(Key::KEY_LEFTCTRL, KeyState::RELEASED).into()])?;
+                }
+                GestureAction::Swipe(direction) => {
+                    let chord = match direction {
+                        SwipeDirection::Left => &self.config.swipe_left,
+                        SwipeDirection::Right => &self.config.swipe_right,
+                        SwipeDirection::Up => &self.config.swipe_up,
+                        SwipeDirection::Down => &self.config.swipe_down,
+                    };
+                    match chord {
+                        Some(chord) => tap_chord(&self.uinput, chord)?,
+                        None => log::debug!("Swipe {:?} has no shortcut configured", direction),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// High-resolution wheel events plus legacy notches for older clients.
+    fn wheel_events(&mut self, vertical: i32, horizontal: i32) -> Vec<InputEvent> {
+        let mut events = Vec::with_capacity(4);
+
+        if vertical != 0 {
+            events.push(RelEvent::new(Rel::WHEEL_HI_RES, vertical).into());
+            self.wheel_acc += vertical;
+            let notches = self.wheel_acc / HI_RES_PER_NOTCH;
+            if notches != 0 {
+                self.wheel_acc -= notches * HI_RES_PER_NOTCH;
+                events.push(RelEvent::new(Rel::WHEEL, notches).into());
+            }
+        }
+        if horizontal != 0 {
+            events.push(RelEvent::new(Rel::HWHEEL_HI_RES, horizontal).into());
+            self.hwheel_acc += horizontal;
+            let notches = self.hwheel_acc / HI_RES_PER_NOTCH;
+            if notches != 0 {
+                self.hwheel_acc -= notches * HI_RES_PER_NOTCH;
+                events.push(RelEvent::new(Rel::HWHEEL, notches).into());
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(slot: usize, x: i32, y: i32) -> Contact {
+        Contact { slot, x, y }
+    }
+
+    #[test]
+    fn test_single_tap_clicks() {
+        let mut g = GestureRecognizer::new(10, true);
+        let t0 = Instant::now();
+        assert!(g.update(&[contact(0, 100, 100)], t0).is_empty());
+        let actions = g.update(&[], t0 + Duration::from_millis(50));
+        assert_eq!(actions, vec![GestureAction::Click(Key::BTN_LEFT)]);
+    }
+
+    #[test]
+    fn test_two_finger_scroll() {
+        let mut g = GestureRecognizer::new(10, true);
+        let t0 = Instant::now();
+        g.update(&[contact(0, 100, 100), contact(1, 200, 100)], t0);
+        // Start threshold, then 10 mm further down.
+        g.update(&[contact(0, 100, 140), contact(1, 200, 140)], t0);
+        let actions = g.update(&[contact(0, 100, 240), contact(1, 200, 240)], t0);
+        assert_eq!(actions, vec![GestureAction::Scroll { vertical: 200, horizontal: 0 }]);
+        // A scroll is never a tap.
+        assert!(g.update(&[], t0).is_empty());
+    }
+
+    #[test]
+    fn test_three_finger_swipe() {
+        let mut g = GestureRecognizer::new(10, true);
+        let t0 = Instant::now();
+        let fingers = |dx: i32| [contact(0, 500 + dx, 100), contact(1, 550 + dx, 100), contact(2, 600 + dx, 100)];
+        g.update(&fingers(0), t0);
+        let actions = g.update(&fingers(-250), t0);
+        assert_eq!(actions, vec![GestureAction::Swipe(SwipeDirection::Left)]);
+        assert!(g.update(&fingers(-400), t0).is_empty());
+    }
+}
@@ -0,0 +1,63 @@
+//! Virtual keyboard/mouse device for synthesized input (gestures, shortcuts).
+
+use evdevil::event::{InputEvent, Key, KeyEvent, KeyState, Rel};
+use evdevil::uinput::UinputDevice;
+use evdevil::{Bus, InputId};
+
+use crate::chord::KeyChord;
+use crate::error::Result;
+
+use super::VIRTUAL_VENDOR_ID;
+
+/// Highest keyboard key code enabled on the device (KEY_MICMUTE).
+const LAST_KEYBOARD_KEY: u16 = 248;
+
+/// Create a combined keyboard and mouse device.
+///
+/// All regular keyboard keys and the three main mouse buttons are enabled, plus
+/// relative axes so that libinput treats it as a pointer and accepts the
+/// buttons and wheel events.
+pub fn create_keyboard_device(
+    name: &str,
+    product_id: u16,
+) -> Result<UinputDevice> {
+    let keys = (1..=LAST_KEYBOARD_KEY)
+        .map(Key::from_raw)
+        .chain([Key::BTN_LEFT, Key::BTN_RIGHT, Key::BTN_MIDDLE]);
+
+    let device = UinputDevice::builder()?
+        .with_input_id(InputId::new(Bus::from_raw(0x03), VIRTUAL_VENDOR_ID, product_id, 0))?
+        .with_keys(keys)?
+        .with_rel_axes([
+            Rel::X,
+            Rel::Y,
+            Rel::WHEEL,
+            Rel::HWHEEL,
+            Rel::WHEEL_HI_RES,
+            Rel::HWHEEL_HI_RES,
+        ])?
+        .build(name)?;
+
+    Ok(device)
+}
+
+/// Events pressing (in order) or releasing (in reverse order) a chord.
+pub fn chord_events(chord: &KeyChord, pressed: bool) -> Vec<InputEvent> {
+    let state = if pressed { KeyState::PRESSED } else { KeyState::RELEASED };
+    let keys: Box<dyn Iterator<Item = &Key>> = if pressed {
+        Box::new(chord.keys().iter())
+    } else {
+        Box::new(chord.keys().iter().rev())
+    };
+    keys.map(|&key| KeyEvent::new(key, state).into()).collect()
+}
+
+/// Press and release a chord as two separate frames.
+pub fn tap_chord(
+    uinput: &UinputDevice,
+    chord: &KeyChord,
+) -> Result<()> {
+    uinput.write(&chord_events(chord, true))?;
+    uinput.write(&chord_events(chord, false))?;
+    Ok(())
+}
@@ -0,0 +1,141 @@
+//! Touch regions acting as buttons: a programmable macro pad.
+//!
+//! A contact that starts inside a `[[touch_regions]]` rectangle is taken out
+//! of the touchpad slots and instead triggers the region's action: a key
+//! chord or mouse button held for as long as the finger stays down, or a
+//! shell command run once on touch.
+
+use std::process::Command;
+
+use evdevil::event::{Key, KeyEvent, KeyState};
+use evdevil::uinput::UinputDevice;
+use serde::Deserialize;
+
+use crate::chord::KeyChord;
+use crate::error::Result;
+
+use super::keyboard::{chord_events, create_keyboard_device};
+use super::zones::Rect;
+use super::MACROPAD_PRODUCT_ID;
+
+/// Name of the virtual macro pad device.
+const MACROPAD_DEVICE_NAME: &str = "reMarkable Macro Pad";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+impl MouseButton {
+    fn key(self) -> Key {
+        match self {
+            MouseButton::Left => Key::BTN_LEFT,
+            MouseButton::Right => Key::BTN_RIGHT,
+            MouseButton::Middle => Key::BTN_MIDDLE,
+        }
+    }
+}
+
+/// One `[[touch_regions]]` entry. Exactly one of `keys`, `button` and
+/// `command` must be set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TouchRegion {
+    /// Area in touch output coordinates.
+    pub rect: Rect,
+    pub keys: Option<KeyChord>,
+    pub button: Option<MouseButton>,
+    pub command: Option<String>,
+}
+
+impl TouchRegion {
+    /// Whether exactly one action is configured.
+    pub fn is_valid(&self) -> bool {
+        let actions = [self.keys.is_some(), self.button.is_some(), self.command.is_some()];
+        actions.iter().filter(|&&set| set).count() == 1
+    }
+}
+
+/// Touch regions plus the virtual device their keys and buttons go to.
+pub struct MacroPad {
+    regions: Vec<TouchRegion>,
+    uinput: UinputDevice,
+    /// Region held down by each touch slot.
+    held: Vec<Option<usize>>,
+}
+
+impl MacroPad {
+    pub fn new(
+        regions: &[TouchRegion],
+        slots: usize,
+    ) -> Result<Self> {
+        let uinput = create_keyboard_device(MACROPAD_DEVICE_NAME, MACROPAD_PRODUCT_ID)?;
+        Ok(Self {
+            regions: regions.to_vec(),
+            uinput,
+            held: vec![None; slots],
+        })
+    }
+
+    /// Index of the region containing a point, if any.
+    pub fn region_at(&self, x: i32, y: i32) -> Option<usize> {
+        self.regions.iter().position(|r| r.rect.contains(x, y))
+    }
+
+    /// Start holding `region` with the contact in `slot`.
+    pub fn press(
+        &mut self,
+        slot: usize,
+        region: usize,
+    ) -> Result<()> {
+        self.held[slot] = Some(region);
+        self.trigger(region, true)
+    }
+
+    /// Release whatever the contact in `slot` was holding.
+    pub fn release(&mut self, slot: usize) -> Result<()> {
+        match self.held[slot].take() {
+            Some(region) => self.trigger(region, false),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_held(&self, slot: usize) -> bool {
+        self.held[slot].is_some()
+    }
+
+    fn trigger(
+        &self,
+        region: usize,
+        pressed: bool,
+    ) -> Result<()> {
+        let region = &self.regions[region];
+
+        if let Some(chord) = &region.keys {
+            self.uinput.write(&chord_events(chord, pressed))?;
+        } else if let Some(button) = region.button {
+            let state = if pressed { KeyState::PRESSED } else { KeyState::RELEASED };
+            self.uinput.write(&[KeyEvent::new(button.key(), state).into()])?;
+        } else if let (Some(command), true) = (&region.command, pressed) {
+            run_command(command);
+        }
+        Ok(())
+    }
+}
+
+/// Run a shell command in the background, reaping it when it exits.
+fn run_command(command: &str) {
+    log::debug!("Running touch region command: {}", command);
+
+    match Command::new("sh").arg("-c").arg(command).spawn() {
+        Ok(mut child) => {
+            std::thread::spawn(move || {
+                let _ = child.wait();
+            });
+        }
+        Err(e) => log::warn!("Failed to run '{}': {}", command, e),
+    }
+}
@@ -1,7 +1,36 @@
 mod event;
+mod gesture;
+mod keyboard;
+mod macropad;
+mod output;
 mod pen;
+mod sink;
+mod source;
 mod touch;
+mod zones;
 
-pub use event::parse_input_event;
-pub use pen::run_pen;
-pub use touch::run_touch;
+pub use event::{parse_event_time, parse_input_event};
+pub use gesture::GestureConfig;
+pub use macropad::TouchRegion;
+pub use output::VirtualDevice;
+pub use pen::{forward_pen, run_pen};
+pub use sink::{Collector, EventSink, Recorder};
+pub use source::{EventSource, SshSource};
+pub use touch::{forward_touch, run_touch, TouchMode};
+pub use zones::ExclusionConfig;
+
+/// USB vendor ID advertised by the virtual devices.
+pub const VIRTUAL_VENDOR_ID: u16 = 0x2d1f;
+/// Product ID of the virtual pen.
+pub const PEN_PRODUCT_ID: u16 = 0x0001;
+/// Product ID of the virtual touch surface.
+pub const TOUCH_PRODUCT_ID: u16 = 0x0002;
+/// Product ID of the virtual gesture keyboard/mouse.
+pub const GESTURE_PRODUCT_ID: u16 = 0x0003;
+/// Product ID of the virtual macro pad keyboard/mouse.
+pub const MACROPAD_PRODUCT_ID: u16 = 0x0004;
+
+/// Name of the virtual pen device.
+pub const PEN_DEVICE_NAME: &str = "reMarkable Pen";
+/// Name of the virtual touch device.
+pub const TOUCH_DEVICE_NAME: &str = "reMarkable Touch";
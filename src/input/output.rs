@@ -0,0 +1,98 @@
+use std::io;
+use std::time::Duration;
+
+use evdevil::event::InputEvent;
+use evdevil::uinput::UinputDevice;
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::error::Result;
+use crate::state::{InputKind, SharedState};
+use crate::transform::CoordinateTransform;
+
+use super::pen::create_pen_device;
+use super::sink::EventSink;
+use super::touch::{create_touch_device, TouchMode};
+
+/// How long to give udev and the desktop to pick up a new device.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+
+/// The virtual pen or touch device. Every frame written to it is also
+/// passed to `rm-pad dump --output` viewers.
+pub struct VirtualDevice {
+    uinput: UinputDevice,
+    kind: InputKind,
+    device: DeviceProfile,
+    touch_mode: TouchMode,
+    state: SharedState,
+}
+
+impl VirtualDevice {
+    /// Create the virtual pen in the current orientation.
+    pub fn pen(config: &Config, device: &DeviceProfile, state: &SharedState) -> Result<Self> {
+        let transform =
+            CoordinateTransform::new(config.pen_transform, state.orientation(), device.pen_x_max, device.pen_y_max);
+        log::info!("Creating pen uinput device");
+        Self::new(InputKind::Pen, device, config.touch_mode, transform, state)
+    }
+
+    /// Create the virtual touch surface in the current orientation.
+    pub fn touch(config: &Config, device: &DeviceProfile, state: &SharedState) -> Result<Self> {
+        let transform =
+            CoordinateTransform::new(config.touch_transform, state.orientation(), device.touch_x_max, device.touch_y_max);
+        log::info!("Creating touch uinput device ({})", config.touch_mode);
+        Self::new(InputKind::Touch, device, config.touch_mode, transform, state)
+    }
+
+    fn new(
+        kind: InputKind,
+        device: &DeviceProfile,
+        touch_mode: TouchMode,
+        transform: CoordinateTransform,
+        state: &SharedState,
+    ) -> Result<Self> {
+        let uinput = create_device(kind, device, touch_mode, transform)?;
+        log_ready(kind, &uinput);
+
+        Ok(Self { uinput, kind, device: *device, touch_mode, state: state.clone() })
+    }
+}
+
+impl EventSink for VirtualDevice {
+    fn write(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        self.uinput.write(events)?;
+        self.state.publish_output(self.kind, events);
+        Ok(())
+    }
+
+    /// Replace the device, since its axis ranges depend on the orientation.
+    fn set_transform(&mut self, transform: CoordinateTransform) -> io::Result<()> {
+        self.uinput = create_device(self.kind, &self.device, self.touch_mode, transform)?;
+        log_ready(self.kind, &self.uinput);
+        self.settle();
+        Ok(())
+    }
+
+    /// Give udev and the desktop time to pick up the new devices.
+    fn settle(&mut self) {
+        std::thread::sleep(SETTLE_TIME);
+    }
+}
+
+fn log_ready(kind: InputKind, uinput: &UinputDevice) {
+    if let Ok(name) = uinput.sysname() {
+        log::info!("Virtual {} device ready: /sys/devices/virtual/input/{}", kind, name.to_string_lossy());
+    }
+}
+
+fn create_device(
+    kind: InputKind,
+    device: &DeviceProfile,
+    touch_mode: TouchMode,
+    transform: CoordinateTransform,
+) -> io::Result<UinputDevice> {
+    match kind {
+        InputKind::Pen => create_pen_device(device, transform),
+        InputKind::Touch => create_touch_device(device, transform, touch_mode),
+    }
+}
@@ -1,28 +1,35 @@
-use std::io::Read;
+use std::io;
 use std::time::Instant;
 
-use evdevil::event::{Abs, InputEvent, Key};
+use evdevil::event::{Abs, AbsEvent, InputEvent, Key, KeyEvent, KeyState};
 use evdevil::uinput::{AbsSetup, UinputDevice};
 use evdevil::{AbsInfo, Bus, InputId, InputProp};
 
 use crate::config::Config;
 use crate::device::DeviceProfile;
-use crate::orientation::Orientation;
+use crate::error::Result;
 use crate::palm::SharedPalmState;
-use crate::ssh;
+use crate::state::{InputKind, SharedState};
+use crate::transform::CoordinateTransform;
 
-use super::event::{key_event, parse_input_event, ABS_PRESSURE, EV_ABS, EV_SYN, SYN_REPORT};
+use super::output::VirtualDevice;
+use super::sink::EventSink;
+use super::source::{EventSource, SshSource};
+use super::{PEN_DEVICE_NAME, PEN_PRODUCT_ID, VIRTUAL_VENDOR_ID};
+use super::event::{
+    key_event, parse_input_event, ABS_DISTANCE, ABS_PRESSURE, EV_ABS, EV_KEY, EV_SYN, SYN_REPORT,
+};
 
 const ABS_X: u16 = 0x00;
 const ABS_Y: u16 = 0x01;
 const ABS_TILT_X: u16 = 0x1a;
 const ABS_TILT_Y: u16 = 0x1b;
 
-fn create_pen_device(device: &DeviceProfile, orientation: Orientation) -> Result<UinputDevice, Box<dyn std::error::Error + Send + Sync>> {
-    let (out_x_max, out_y_max) = orientation.pen_output_dimensions(device.pen_x_max, device.pen_y_max);
+pub(super) fn create_pen_device(device: &DeviceProfile, transform: CoordinateTransform) -> std::io::Result<UinputDevice> {
+    let (out_x_max, out_y_max) = transform.pen_output_dimensions(device.pen_x_max, device.pen_y_max);
     let axes = [
-        AbsSetup::new(Abs::X, AbsInfo::new(0, out_x_max).with_resolution(100)),
-        AbsSetup::new(Abs::Y, AbsInfo::new(0, out_y_max).with_resolution(100)),
+        AbsSetup::new(Abs::X, AbsInfo::new(0, out_x_max).with_resolution(device.pen_resolution)),
+        AbsSetup::new(Abs::Y, AbsInfo::new(0, out_y_max).with_resolution(device.pen_resolution)),
         AbsSetup::new(Abs::PRESSURE, AbsInfo::new(0, device.pen_pressure_max)),
         AbsSetup::new(Abs::DISTANCE, AbsInfo::new(0, device.pen_distance_max)),
         AbsSetup::new(Abs::TILT_X, AbsInfo::new(-device.pen_tilt_range, device.pen_tilt_range)),
@@ -30,37 +37,70 @@ fn create_pen_device(device: &DeviceProfile, orientation: Orientation) -> Result
     ];
 
     let device = UinputDevice::builder()?
-        .with_input_id(InputId::new(Bus::from_raw(0x03), 0x2d1f, 0x0001, 0))?
+        .with_input_id(InputId::new(Bus::from_raw(0x03), VIRTUAL_VENDOR_ID, PEN_PRODUCT_ID, 0))?
         .with_props([InputProp::DIRECT])?
         .with_abs_axes(axes)?
         .with_keys([Key::BTN_TOOL_PEN, Key::BTN_TOUCH, Key::BTN_STYLUS])?
-        .build("reMarkable Pen")?;
+        .build(PEN_DEVICE_NAME)?;
 
     Ok(device)
 }
 
+/// Lift the pen and take it out of proximity, e.g. before replacing the device.
+fn release_pen(sink: &mut impl EventSink) -> Result<()> {
+    sink.write(&[
+        AbsEvent::new(Abs::PRESSURE, 0).into(),
+        KeyEvent::new(Key::BTN_TOUCH, KeyState::RELEASED).into(),
+        KeyEvent::new(Key::BTN_STYLUS, KeyState::RELEASED).into(),
+        KeyEvent::new(Key::BTN_TOOL_PEN, KeyState::RELEASED).into(),
+    ])?;
+    Ok(())
+}
+
 pub fn run_pen(
     config: &Config,
     device_profile: &DeviceProfile,
+    state: &SharedState,
     palm: Option<SharedPalmState>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (_cleanup, mut channel) =
-        ssh::open_input_stream(&config.pen_device, config, config.grab_input)?;
+) -> Result<()> {
+    let grabbed = state.grab();
+    let mut source = SshSource::open(&config.pen_device, config, grabbed)?;
+    state.set_stream(InputKind::Pen, source.connection()?);
+    let mut uinput = VirtualDevice::pen(config, device_profile, state)?;
 
-    log::info!("Creating pen uinput device");
-    let uinput = create_pen_device(device_profile, config.orientation)?;
-
-    if let Ok(name) = uinput.sysname() {
-        log::info!("Pen device ready: /sys/devices/virtual/input/{}", name.to_string_lossy());
-    }
+    forward_pen(&mut source, &mut uinput, config, device_profile, state, palm, grabbed)
+}
 
-    std::thread::sleep(std::time::Duration::from_secs(1));
+/// Forward raw pen events from `source` to `sink` until the source ends
+/// or the grab setting no longer matches `grabbed`.
+pub fn forward_pen(
+    source: &mut impl EventSource,
+    sink: &mut impl EventSink,
+    config: &Config,
+    device_profile: &DeviceProfile,
+    state: &SharedState,
+    palm: Option<SharedPalmState>,
+    grabbed: bool,
+) -> Result<()> {
+    let mut transform = CoordinateTransform::new(
+        config.pen_transform,
+        state.orientation(),
+        device_profile.pen_x_max,
+        device_profile.pen_y_max,
+    );
+    sink.settle();
+    state.set_connected(InputKind::Pen, true);
     log::info!("Pen forwarding started");
 
     let btn_touch_code = Key::BTN_TOUCH.raw();
+    let btn_tool_pen_code = Key::BTN_TOOL_PEN.raw();
+    let btn_tool_rubber_code = Key::BTN_TOOL_RUBBER.raw();
     let mut buf = vec![0u8; device_profile.input_event_size];
     let mut batch: Vec<InputEvent> = Vec::with_capacity(32);
     let mut touch_down = false;
+    let mut forwarding = true;
+    // BTN_TOOL_PEN was released on the virtual device while the pen stayed in range
+    let mut restore_tool = false;
     let mut frame_count: u64 = 0;
 
     // For collecting X/Y/tilt values within a frame
@@ -68,12 +108,34 @@ pub fn run_pen(
     let mut pending_y: Option<i32> = None;
     let mut pending_tilt_x: Option<i32> = None;
     let mut pending_tilt_y: Option<i32> = None;
-    let orientation = config.orientation;
+
+    // Proximity, hover distance and last position (normalized output
+    // coordinates) published for palm rejection
+    let mut in_range = false;
+    let mut distance: Option<i32> = None;
+    let mut position: Option<(f64, f64)> = None;
 
     loop {
-        channel.read_exact(&mut buf)?;
+        // Lift the pen if the stream ends mid-stroke, e.g. on shutdown or
+        // when the connection was closed to change the grab setting
+        let read = source.read_event(&mut buf);
+        state.report_progress(InputKind::Pen);
+        if let Err(e) = read {
+            // Idle: the pen stays where it is
+            if e.kind() == io::ErrorKind::TimedOut && state.grab() == grabbed {
+                continue;
+            }
+            release_pen(sink)?;
+            update_palm_state(&palm, false, false, None, None);
+            if state.grab() != grabbed {
+                return Ok(());
+            }
+            return Err(e.into());
+        }
 
+        state.count_event(InputKind::Pen, buf.len());
         let Some(ev) = parse_input_event(&buf) else {
+            state.count_parse_failure(InputKind::Pen);
             continue;
         };
 
@@ -110,20 +172,86 @@ pub fn run_pen(
             continue;
         }
 
+        // Reopen the stream with or without grabbing
+        if state.grab() != grabbed {
+            release_pen(sink)?;
+            update_palm_state(&palm, false, false, None, None);
+            return Ok(());
+        }
+
+        // Drop frames while paused or disabled, lifting the pen first
+        if !state.forwarding(InputKind::Pen) {
+            if forwarding {
+                release_pen(sink)?;
+                update_palm_state(&palm, false, false, None, None);
+                state.set_contacts(InputKind::Pen, 0);
+                touch_down = false;
+                restore_tool = true;
+                forwarding = false;
+            }
+            // Keep tracking proximity so the tool can be restored on resume
+            in_range = pending_x.is_some() || batch.iter().fold(in_range, |in_range, e| {
+                match (e.event_type().raw(), e.raw_code()) {
+                    (EV_KEY, c) if c == btn_tool_pen_code || c == btn_tool_rubber_code => e.raw_value() != 0,
+                    _ => in_range,
+                }
+            });
+            batch.clear();
+            (pending_x, pending_y, pending_tilt_x, pending_tilt_y) = (None, None, None, None);
+            continue;
+        }
+        forwarding = true;
+
+        // Switch orientation between frames by replacing the device, since
+        // its axis ranges depend on the orientation. A configured
+        // pen_transform doesn't follow the orientation.
+        let new_transform = transform.with_orientation(state.orientation());
+        if new_transform != transform {
+            log::info!("Switching pen output to {}", state.orientation());
+            release_pen(sink)?;
+            restore_tool = true;
+            sink.set_transform(new_transform)?;
+            transform = new_transform;
+            touch_down = false;
+        }
+
         // Transform and emit position events
         if let (Some(x), Some(y)) = (pending_x.take(), pending_y.take()) {
-            let (out_x, out_y) = orientation.transform_pen(
+            let (out_x, out_y) = transform.transform_pen(
                 x, y,
                 device_profile.pen_x_max,
                 device_profile.pen_y_max,
             );
             batch.insert(0, InputEvent::new(evdevil::event::EventType::from_raw(EV_ABS), Abs::X.raw(), out_x));
             batch.insert(1, InputEvent::new(evdevil::event::EventType::from_raw(EV_ABS), Abs::Y.raw(), out_y));
+            position = Some((
+                x.clamp(0, device_profile.pen_x_max) as f64 / device_profile.pen_x_max as f64,
+                y.clamp(0, device_profile.pen_y_max) as f64 / device_profile.pen_y_max as f64,
+            ));
+            in_range = true;
+        }
+
+        for e in &batch {
+            let code = e.raw_code();
+            match e.event_type().raw() {
+                EV_KEY if code == btn_tool_pen_code || code == btn_tool_rubber_code => {
+                    in_range = e.raw_value() != 0;
+                }
+                EV_ABS if code == ABS_DISTANCE => distance = Some(e.raw_value()),
+                _ => {}
+            }
         }
+        if !in_range {
+            position = None;
+            distance = None;
+        } else if restore_tool {
+            batch.insert(0, key_event(btn_tool_pen_code, 1));
+        }
+        restore_tool = false;
 
         // Transform and emit tilt events
         if let (Some(tx), Some(ty)) = (pending_tilt_x.take(), pending_tilt_y.take()) {
-            let (out_tx, out_ty) = orientation.transform_tilt(tx, ty);
+            let (out_tx, out_ty) = transform.transform_tilt(tx, ty, device_profile.pen_tilt_range);
             batch.insert(0, InputEvent::new(evdevil::event::EventType::from_raw(EV_ABS), Abs::TILT_X.raw(), out_tx));
             batch.insert(1, InputEvent::new(evdevil::event::EventType::from_raw(EV_ABS), Abs::TILT_Y.raw(), out_ty));
         }
@@ -135,7 +263,8 @@ pub fn run_pen(
             .unwrap_or(0);
 
         let now_touching = pressure > 0;
-        update_palm_state(&palm, now_touching);
+        update_palm_state(&palm, now_touching, in_range, distance, position);
+        state.set_contacts(InputKind::Pen, now_touching as usize);
 
         if now_touching != touch_down {
             let key_ev = key_event(btn_touch_code, if now_touching { 1 } else { 0 });
@@ -147,8 +276,9 @@ pub fn run_pen(
             log::info!("Pen events flowing");
         }
         frame_count += 1;
+        state.count_frame(InputKind::Pen);
 
-        uinput.write(&batch)?;
+        sink.write(&batch)?;
         batch.clear();
 
         if frame_count.is_multiple_of(500) {
@@ -157,12 +287,22 @@ pub fn run_pen(
     }
 }
 
-fn update_palm_state(palm: &Option<SharedPalmState>, now_touching: bool) {
+fn update_palm_state(
+    palm: &Option<SharedPalmState>,
+    now_touching: bool,
+    in_range: bool,
+    distance: Option<i32>,
+    position: Option<(f64, f64)>,
+) {
     let Some(palm_state) = palm else { return };
     let Ok(mut state) = palm_state.lock() else { return };
 
-    state.pen_down = now_touching;
-    if !now_touching {
+    // The grace period starts when the pen lifts, not on every frame without it
+    if state.pen_down && !now_touching {
         state.last_pen_up = Some(Instant::now());
     }
+    state.pen_down = now_touching;
+    state.pen_in_range = in_range;
+    state.pen_distance = distance;
+    state.pen_position = position;
 }
@@ -0,0 +1,57 @@
+//! Where the pen and touch pipelines write their frames: the virtual
+//! devices ([`VirtualDevice`](super::VirtualDevice)), a file
+//! ([`Recorder`]) or memory ([`Collector`]).
+
+use std::io::{self, Write};
+
+use evdevil::event::InputEvent;
+
+use crate::state::{InputKind, OutputFrame};
+use crate::transform::CoordinateTransform;
+
+pub trait EventSink {
+    /// Write `events` as one frame, followed by `SYN_REPORT`.
+    fn write(&mut self, events: &[InputEvent]) -> io::Result<()>;
+
+    /// Called between frames when the orientation changes the output axes.
+    fn set_transform(&mut self, _transform: CoordinateTransform) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called once all of a pipeline's output devices exist, before the
+    /// first frame.
+    fn settle(&mut self) {}
+}
+
+/// Keeps every frame, e.g. to check a pipeline's output in tests.
+#[derive(Debug, Default)]
+pub struct Collector {
+    pub frames: Vec<Vec<InputEvent>>,
+}
+
+impl EventSink for Collector {
+    fn write(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        self.frames.push(events.to_vec());
+        Ok(())
+    }
+}
+
+/// Writes every frame as a JSON line, in the format the control socket's
+/// `watch-output` request streams.
+pub struct Recorder<W: Write> {
+    writer: W,
+    input: InputKind,
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(writer: W, input: InputKind) -> Self {
+        Self { writer, input }
+    }
+}
+
+impl<W: Write> EventSink for Recorder<W> {
+    fn write(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &OutputFrame::new(self.input, events))?;
+        self.writer.write_all(b"\n")
+    }
+}
@@ -0,0 +1,70 @@
+//! Where the pen and touch pipelines read raw `input_event`s from.
+//!
+//! Anything readable is a source: a device on the tablet streamed over SSH
+//! ([`SshSource`]), a local evdev node or captured file, a recording's
+//! [`ReplayStream`](crate::recording::ReplayStream) or a `TcpStream`. Events
+//! must have the device profile's `input_event_size`.
+
+use std::io::{self, Read};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::ssh::{self, GrabCleanup};
+
+/// How long an [`SshSource`] waits for input before reporting that it is
+/// idle, so the forwarding threads can show they are still alive.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub trait EventSource {
+    /// Fill `buf` with the next raw event. Fails with `UnexpectedEof` once
+    /// the source runs out, and with `TimedOut` when a source with a read
+    /// timeout is idle; nothing is consumed then, so reading can go on.
+    fn read_event(&mut self, buf: &mut [u8]) -> io::Result<()>;
+}
+
+impl<R: Read + ?Sized> EventSource for R {
+    fn read_event(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.read(&mut buf[filled..]) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => filled += n,
+                // Report idling between events only; within one, the rest
+                // is on its way
+                Err(e) if e.kind() == io::ErrorKind::TimedOut && filled == 0 => return Err(e),
+                Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::Interrupted) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A device on the tablet, streamed over SSH. Reads time out after
+/// [`IDLE_TIMEOUT`] without input.
+pub struct SshSource {
+    channel: ssh2::Channel,
+    cleanup: GrabCleanup,
+}
+
+impl SshSource {
+    /// Stream `device_path`, grabbing it first with `grab`.
+    pub fn open(device_path: &str, config: &Config, grab: bool) -> Result<Self> {
+        let (cleanup, channel) = ssh::open_input_stream(device_path, config, grab)?;
+        cleanup.set_timeout(IDLE_TIMEOUT);
+        Ok(Self { channel, cleanup })
+    }
+
+    /// A handle to the connection, e.g. to shut it down from another thread.
+    pub fn connection(&self) -> io::Result<TcpStream> {
+        self.cleanup.stream()
+    }
+}
+
+impl Read for SshSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.channel.read(buf)
+    }
+}
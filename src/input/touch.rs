@@ -1,23 +1,83 @@
-use std::io::Read;
-use std::time::Duration;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
 
 use evdevil::event::{Abs, Key, KeyEvent, KeyState};
 use evdevil::uinput::{AbsSetup, UinputDevice};
-use evdevil::{AbsInfo, InputProp, Slot};
+use evdevil::{AbsInfo, Bus, InputId, InputProp};
+use serde::Deserialize;
 
 use crate::config::Config;
 use crate::device::DeviceProfile;
+use crate::error::Result;
 use crate::orientation::Orientation;
-use crate::palm::SharedPalmState;
-use crate::ssh;
-
+use crate::palm::{in_palm_region, PalmConfig, PalmMode, SharedPalmState, PALM_REGION_MARGIN_MM};
+use crate::state::{InputKind, SharedState};
+use crate::transform::CoordinateTransform;
+
+use super::gesture::{Contact, Gestures};
+use super::macropad::MacroPad;
+use super::output::VirtualDevice;
+use super::sink::EventSink;
+use super::source::{EventSource, SshSource};
+use super::zones::ExclusionZones;
+use super::{TOUCH_DEVICE_NAME, TOUCH_PRODUCT_ID, VIRTUAL_VENDOR_ID};
 use super::event::{
-    parse_input_event, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_SLOT, ABS_MT_TRACKING_ID,
+    parse_input_event, ABS_MT_ORIENTATION, ABS_MT_POSITION_X, ABS_MT_POSITION_Y, ABS_MT_PRESSURE,
+    ABS_MT_SLOT, ABS_MT_TOOL_TYPE, ABS_MT_TOUCH_MAJOR, ABS_MT_TOUCH_MINOR, ABS_MT_TRACKING_ID,
     EV_ABS, EV_KEY, EV_SYN, SYN_REPORT,
 };
 
 const MT_SLOTS: usize = 16;
 
+/// Highest tool type advertised (MT_TOOL_PALM), so palm contacts pass through.
+const MT_TOOL_PALM: i32 = 2;
+
+/// How the touch surface is presented to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TouchMode {
+    /// Indirect touchpad: moves the pointer, gestures handled by the compositor.
+    #[default]
+    Touchpad,
+    /// Direct touchscreen: touches land at their position on the mapped output.
+    Touchscreen,
+}
+
+impl fmt::Display for TouchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TouchMode::Touchpad => write!(f, "touchpad"),
+            TouchMode::Touchscreen => write!(f, "touchscreen"),
+        }
+    }
+}
+
+impl FromStr for TouchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "touchpad" => Ok(TouchMode::Touchpad),
+            "touchscreen" => Ok(TouchMode::Touchscreen),
+            _ => Err(format!(
+                "Invalid touch mode '{}'. Valid values: touchpad, touchscreen",
+                s
+            )),
+        }
+    }
+}
+
+/// Contact size, pressure and tool type of a slot, as last reported.
+#[derive(Debug, Clone, Copy, Default)]
+struct ContactShape {
+    major: Option<i32>,
+    minor: Option<i32>,
+    orientation: Option<i32>,
+    pressure: Option<i32>,
+    tool_type: Option<i32>,
+}
+
 struct SlotState {
     x: [Option<i32>; MT_SLOTS],
     y: [Option<i32>; MT_SLOTS],
@@ -25,6 +85,16 @@ struct SlotState {
     last_y: [Option<i32>; MT_SLOTS],
     active: [bool; MT_SLOTS],
     tracking_id: [Option<i32>; MT_SLOTS],
+    shape: [ContactShape; MT_SLOTS],
+    /// Contacts dropped for the rest of their lifetime (palms).
+    rejected: [bool; MT_SLOTS],
+    /// Contacts that just appeared on the tablet and haven't been checked
+    /// against touch regions and exclusion zones yet, because they have no
+    /// position so far.
+    new_contact: [bool; MT_SLOTS],
+    /// Touch regions that contacts started in during palm suppression, to
+    /// press once it ends.
+    pending_region: [Option<usize>; MT_SLOTS],
 }
 
 impl SlotState {
@@ -36,6 +106,10 @@ impl SlotState {
             last_y: [None; MT_SLOTS],
             active: [false; MT_SLOTS],
             tracking_id: [None; MT_SLOTS],
+            shape: [ContactShape::default(); MT_SLOTS],
+            rejected: [false; MT_SLOTS],
+            new_contact: [false; MT_SLOTS],
+            pending_region: [None; MT_SLOTS],
         }
     }
 
@@ -44,12 +118,25 @@ impl SlotState {
         self.y[slot] = None;
         self.last_x[slot] = None;
         self.last_y[slot] = None;
+        self.shape[slot] = ContactShape::default();
+        self.rejected[slot] = false;
+        self.new_contact[slot] = false;
+        self.pending_region[slot] = None;
     }
 
     fn active_count(&self) -> i32 {
         self.active.iter().filter(|&&a| a).count() as i32
     }
 
+    /// Whether a slot is active and not rejected, i.e. sent to the host.
+    fn forwarded(&self, slot: usize) -> bool {
+        self.active[slot] && !self.rejected[slot]
+    }
+
+    fn forwarded_count(&self) -> i32 {
+        (0..MT_SLOTS).filter(|&s| self.forwarded(s)).count() as i32
+    }
+
     fn get_position(&self, slot: usize) -> Option<(i32, i32)> {
         match (self.x[slot], self.y[slot]) {
             (Some(x), Some(y)) => Some((x, y)),
@@ -60,12 +147,29 @@ impl SlotState {
         }
     }
 
-    fn get_primary_position(&self, device: &DeviceProfile, orientation: Orientation) -> Option<(i32, i32)> {
+    /// Forwarded contacts transformed to output coordinates.
+    fn output_contacts(&self, device: &DeviceProfile, transform: CoordinateTransform) -> Vec<Contact> {
         (0..MT_SLOTS)
-            .find(|&s| self.active[s])
+            .filter(|&s| self.forwarded(s))
+            .filter_map(|s| self.get_position(s).map(|pos| (s, pos)))
+            .map(|(slot, (ax, ay))| {
+                let (x, y) = transform.transform_touch(
+                    ax.clamp(0, device.touch_x_max),
+                    ay.clamp(0, device.touch_y_max),
+                    device.touch_x_max,
+                    device.touch_y_max,
+                );
+                Contact { slot, x, y }
+            })
+            .collect()
+    }
+
+    fn get_primary_position(&self, device: &DeviceProfile, transform: CoordinateTransform) -> Option<(i32, i32)> {
+        (0..MT_SLOTS)
+            .find(|&s| self.forwarded(s))
             .and_then(|s| self.x[s].zip(self.y[s]))
             .map(|(ax, ay)| {
-                orientation.transform_touch(
+                transform.transform_touch(
                     ax.clamp(0, device.touch_x_max),
                     ay.clamp(0, device.touch_y_max),
                     device.touch_x_max,
@@ -75,6 +179,30 @@ impl SlotState {
     }
 }
 
+/// Settings for one run of the touch event loop.
+struct TouchSettings {
+    transform: CoordinateTransform,
+    palm: PalmConfig,
+    exclusion: ExclusionZones,
+}
+
+impl TouchSettings {
+    fn new(config: &Config, device: &DeviceProfile, transform: CoordinateTransform) -> Self {
+        let (out_x_max, out_y_max) = transform.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+
+        Self {
+            transform,
+            palm: config.palm_config(device),
+            exclusion: ExclusionZones::new(
+                &config.touch_exclusion,
+                out_x_max + 1,
+                out_y_max + 1,
+                device.touch_resolution,
+            ),
+        }
+    }
+}
+
 struct FrameState {
     current_slot: usize,
     contact_count: i32,
@@ -91,8 +219,12 @@ impl FrameState {
     }
 }
 
-fn create_touchpad_device(device: &DeviceProfile, orientation: Orientation) -> Result<UinputDevice, Box<dyn std::error::Error + Send + Sync>> {
-    let (out_x_max, out_y_max) = orientation.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+pub(super) fn create_touch_device(
+    device: &DeviceProfile,
+    transform: CoordinateTransform,
+    mode: TouchMode,
+) -> std::io::Result<UinputDevice> {
+    let (out_x_max, out_y_max) = transform.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
     let resolution = device.touch_resolution;
 
     let axes = [
@@ -102,63 +234,139 @@ fn create_touchpad_device(device: &DeviceProfile, orientation: Orientation) -> R
         AbsSetup::new(Abs::MT_TRACKING_ID, AbsInfo::new(-1, i32::MAX)),
         AbsSetup::new(Abs::MT_POSITION_X, AbsInfo::new(0, out_x_max).with_resolution(resolution)),
         AbsSetup::new(Abs::MT_POSITION_Y, AbsInfo::new(0, out_y_max).with_resolution(resolution)),
+        AbsSetup::new(Abs::MT_TOUCH_MAJOR, AbsInfo::new(0, device.touch_major_max)),
+        AbsSetup::new(Abs::MT_TOUCH_MINOR, AbsInfo::new(0, device.touch_major_max)),
+        AbsSetup::new(
+            Abs::MT_ORIENTATION,
+            AbsInfo::new(-device.touch_orientation_max, device.touch_orientation_max),
+        ),
+        AbsSetup::new(Abs::MT_PRESSURE, AbsInfo::new(0, device.touch_pressure_max)),
+        AbsSetup::new(Abs::MT_TOOL_TYPE, AbsInfo::new(0, MT_TOOL_PALM)),
     ];
 
-    let device = UinputDevice::builder()?
-        .with_props([InputProp::POINTER, InputProp::BUTTONPAD])?
-        .with_abs_axes(axes)?
-        .with_keys([
-            Key::BTN_LEFT,
-            Key::BTN_TOUCH,
-            Key::BTN_TOOL_FINGER,
-            Key::BTN_TOOL_DOUBLETAP,
-            Key::BTN_TOOL_TRIPLETAP,
-            Key::BTN_TOOL_QUADTAP,
-        ])?
-        .build("reMarkable Touch")?;
+    let builder = UinputDevice::builder()?
+        .with_input_id(InputId::new(Bus::from_raw(0x03), VIRTUAL_VENDOR_ID, TOUCH_PRODUCT_ID, 0))?;
+
+    // Touchscreens only report BTN_TOUCH; the kernel drops the BTN_TOOL_*
+    // events emitted by the shared frame code since they aren't enabled.
+    let builder = match mode {
+        TouchMode::Touchpad => builder
+            .with_props([InputProp::POINTER, InputProp::BUTTONPAD])?
+            .with_keys([
+                Key::BTN_LEFT,
+                Key::BTN_TOUCH,
+                Key::BTN_TOOL_FINGER,
+                Key::BTN_TOOL_DOUBLETAP,
+                Key::BTN_TOOL_TRIPLETAP,
+                Key::BTN_TOOL_QUADTAP,
+            ])?,
+        TouchMode::Touchscreen => builder
+            .with_props([InputProp::DIRECT])?
+            .with_keys([Key::BTN_TOUCH])?,
+    };
+
+    let device = builder.with_abs_axes(axes)?.build(TOUCH_DEVICE_NAME)?;
 
     Ok(device)
 }
 
+/// Devices the touch thread writes to.
+struct TouchOutputs<'a, S> {
+    sink: &'a mut S,
+    gestures: Option<Gestures>,
+    macropad: Option<MacroPad>,
+}
+
 pub fn run_touch(
     config: &Config,
     device_profile: &DeviceProfile,
+    state: &SharedState,
     palm: Option<SharedPalmState>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let (_cleanup, mut channel) =
-        ssh::open_input_stream(&config.touch_device, config, config.grab_input)?;
+) -> Result<()> {
+    let grabbed = state.grab();
+    let mut source = SshSource::open(&config.touch_device, config, grabbed)?;
+    state.set_stream(InputKind::Touch, source.connection()?);
+    let mut uinput = VirtualDevice::touch(config, device_profile, state)?;
 
-    log::info!("Creating touch uinput device");
-    let uinput = create_touchpad_device(device_profile, config.orientation)?;
+    forward_touch(&mut source, &mut uinput, config, device_profile, state, palm, grabbed)
+}
 
-    if let Ok(name) = uinput.sysname() {
-        log::info!("Touch device ready: /sys/devices/virtual/input/{}", name.to_string_lossy());
-    }
+/// Forward raw touch events from `source` to `sink`, and to new gesture
+/// and macro pad devices if configured, until the source ends or the grab
+/// setting no longer matches `grabbed`.
+pub fn forward_touch(
+    source: &mut impl EventSource,
+    sink: &mut impl EventSink,
+    config: &Config,
+    device_profile: &DeviceProfile,
+    state: &SharedState,
+    palm: Option<SharedPalmState>,
+    grabbed: bool,
+) -> Result<()> {
+    let gestures = if config.gestures.enabled {
+        log::info!("Creating gesture uinput device");
+        Some(Gestures::new(&config.gestures, device_profile.touch_resolution)?)
+    } else {
+        None
+    };
+
+    let macropad = if config.touch_regions.is_empty() {
+        None
+    } else {
+        log::info!("Creating macro pad uinput device ({} regions)", config.touch_regions.len());
+        Some(MacroPad::new(&config.touch_regions, MT_SLOTS)?)
+    };
 
-    std::thread::sleep(Duration::from_secs(1));
+    // Once for all of the thread's devices
+    sink.settle();
+    state.set_connected(InputKind::Touch, true);
     log::info!("Touch forwarding started");
 
-    run_event_loop(&mut channel, &uinput, device_profile, config.orientation, palm, config.palm_grace_ms)
+    let mut outputs = TouchOutputs { sink, gestures, macropad };
+    run_event_loop(source, &mut outputs, config, device_profile, state, grabbed, palm)
 }
 
 fn run_event_loop(
-    channel: &mut impl Read,
-    uinput: &UinputDevice,
+    source: &mut impl EventSource,
+    outputs: &mut TouchOutputs<impl EventSink>,
+    config: &Config,
     device: &DeviceProfile,
-    orientation: Orientation,
+    state: &SharedState,
+    grabbed: bool,
     palm: Option<SharedPalmState>,
-    grace_ms: u64,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<()> {
+    let mut settings = TouchSettings::new(
+        config,
+        device,
+        CoordinateTransform::new(config.touch_transform, state.orientation(), device.touch_x_max, device.touch_y_max),
+    );
     let mut buf = vec![0u8; device.input_event_size];
     let mut slots = SlotState::new();
     let mut frame = FrameState::new();
     let mut next_tracking_id: i32 = 0;
     let mut frame_count: u64 = 0;
+    let mut forwarding = true;
 
     loop {
-        channel.read_exact(&mut buf)?;
+        // Lift all contacts if the stream ends mid-touch, e.g. on shutdown or
+        // when the connection was closed to change the grab setting
+        let read = source.read_event(&mut buf);
+        state.report_progress(InputKind::Touch);
+        if let Err(e) = read {
+            // Idle: contacts stay where they are
+            if e.kind() == io::ErrorKind::TimedOut && state.grab() == grabbed {
+                continue;
+            }
+            release_all(outputs, &mut slots)?;
+            if state.grab() != grabbed {
+                return Ok(());
+            }
+            return Err(e.into());
+        }
 
+        state.count_event(InputKind::Touch, buf.len());
         let Some(ev) = parse_input_event(&buf) else {
+            state.count_parse_failure(InputKind::Touch);
             continue;
         };
 
@@ -181,15 +389,75 @@ fn run_event_loop(
         resolve_pending_positions(&mut slots, &frame);
         frame.pending_positions.clear();
 
-        let contact_count = slots.active_count();
+        // Reopen the stream with or without grabbing
+        if state.grab() != grabbed {
+            release_all(outputs, &mut slots)?;
+            return Ok(());
+        }
+
+        // Drop frames while paused or disabled, lifting all contacts first
+        if !state.forwarding(InputKind::Touch) {
+            if forwarding {
+                release_all(outputs, &mut slots)?;
+                state.set_contacts(InputKind::Touch, 0);
+                forwarding = false;
+            }
+            continue;
+        }
+        forwarding = true;
+
+        // Switch orientation between frames: lift all contacts on the old
+        // device, then replace it since its axis ranges depend on orientation.
+        // A configured touch_transform doesn't follow the orientation.
+        let new_transform = settings.transform.with_orientation(state.orientation());
+        if new_transform != settings.transform {
+            log::info!("Switching touch output to {}", state.orientation());
+            emit_palm_suppression(outputs.sink, &mut slots)?;
+            if let Some(gestures) = outputs.gestures.as_mut() {
+                gestures.cancel()?;
+            }
+            outputs.sink.set_transform(new_transform)?;
+            settings = TouchSettings::new(config, device, new_transform);
+        }
+        let transform = settings.transform;
+        let palm_config = &settings.palm;
+        let TouchOutputs { sink, gestures, macropad } = &mut *outputs;
+
+        let suppress = palm_config.mode == PalmMode::Global && should_suppress_palm(&palm, palm_config);
+
+        if let Some(macropad) = macropad.as_mut() {
+            apply_touch_regions(&mut slots, macropad, device, transform, suppress)?;
+        }
+        if !settings.exclusion.is_empty() {
+            apply_exclusion_zones(&mut slots, &settings.exclusion, device, transform);
+        }
+        for slot in 0..MT_SLOTS {
+            if slots.get_position(slot).is_some() {
+                slots.new_contact[slot] = false;
+            }
+        }
+        if palm_config.mode == PalmMode::Smart && !config.no_palm_rejection {
+            reject_palm_contacts(&mut slots, &palm, palm_config, device, state.orientation());
+        }
+        let contact_count = slots.forwarded_count();
 
-        if should_suppress_palm(&palm, grace_ms) {
-            emit_palm_suppression(uinput, &mut slots)?;
+        if suppress {
+            emit_palm_suppression(*sink, &mut slots)?;
+            if let Some(gestures) = gestures.as_mut() {
+                gestures.cancel()?;
+            }
+            state.count_palm_suppressed(InputKind::Touch);
+            state.set_contacts(InputKind::Touch, 0);
             log_frame_progress(&mut frame_count, 0, true);
             continue;
         }
 
-        emit_touch_frame(uinput, &mut slots, &mut next_tracking_id, device, orientation)?;
+        emit_touch_frame(*sink, &mut slots, &mut next_tracking_id, device, transform)?;
+        if let Some(gestures) = gestures.as_mut() {
+            gestures.process(&slots.output_contacts(device, transform))?;
+        }
+        state.count_frame(InputKind::Touch);
+        state.set_contacts(InputKind::Touch, contact_count as usize);
         log_frame_progress(&mut frame_count, contact_count, false);
     }
 }
@@ -204,6 +472,7 @@ fn process_abs_event(slots: &mut SlotState, frame: &mut FrameState, code: u16, v
             if value >= 0 {
                 if !slots.active[slot] {
                     frame.contact_count += 1;
+                    slots.new_contact[slot] = true;
                 }
                 slots.active[slot] = true;
             } else {
@@ -228,6 +497,11 @@ fn process_abs_event(slots: &mut SlotState, frame: &mut FrameState, code: u16, v
                 frame.pending_positions.push((x, value));
             }
         }
+        ABS_MT_TOUCH_MAJOR => slots.shape[frame.current_slot].major = Some(value),
+        ABS_MT_TOUCH_MINOR => slots.shape[frame.current_slot].minor = Some(value),
+        ABS_MT_ORIENTATION => slots.shape[frame.current_slot].orientation = Some(value),
+        ABS_MT_PRESSURE => slots.shape[frame.current_slot].pressure = Some(value),
+        ABS_MT_TOOL_TYPE => slots.shape[frame.current_slot].tool_type = Some(value),
         _ => {}
     }
 }
@@ -237,6 +511,7 @@ fn activate_slot_if_needed(slots: &mut SlotState, frame: &mut FrameState, slot:
         return;
     }
     slots.active[slot] = true;
+    slots.new_contact[slot] = true;
     frame.contact_count += 1;
 }
 
@@ -262,58 +537,185 @@ fn resolve_pending_positions(slots: &mut SlotState, frame: &FrameState) {
     }
 }
 
-fn should_suppress_palm(palm: &Option<SharedPalmState>, grace_ms: u64) -> bool {
+/// Hand contacts that start inside a touch region to the macro pad, and
+/// release regions whose contact has lifted. A contact that starts during
+/// palm suppression still belongs to its region, but only presses it once
+/// suppression ends.
+fn apply_touch_regions(
+    slots: &mut SlotState,
+    macropad: &mut MacroPad,
+    device: &DeviceProfile,
+    transform: CoordinateTransform,
+    suppress: bool,
+) -> Result<()> {
+    for slot in 0..MT_SLOTS {
+        if macropad.is_held(slot) && !slots.active[slot] {
+            macropad.release(slot)?;
+        }
+    }
+
+    for contact in slots.output_contacts(device, transform) {
+        if !slots.new_contact[contact.slot] {
+            continue;
+        }
+        if let Some(region) = macropad.region_at(contact.x, contact.y) {
+            slots.rejected[contact.slot] = true;
+            slots.pending_region[contact.slot] = Some(region);
+        }
+    }
+
+    if suppress {
+        return Ok(());
+    }
+    for slot in 0..MT_SLOTS {
+        if let Some(region) = slots.pending_region[slot].take() {
+            log::debug!("Contact in slot {} pressed touch region {}", slot, region);
+            macropad.press(slot, region)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Drop contacts that start inside an exclusion zone, for their whole lifetime.
+///
+/// A contact starts when it first appears on the tablet, whether or not it
+/// is forwarded then, e.g. during palm suppression.
+fn apply_exclusion_zones(
+    slots: &mut SlotState,
+    zones: &ExclusionZones,
+    device: &DeviceProfile,
+    transform: CoordinateTransform,
+) {
+    for contact in slots.output_contacts(device, transform) {
+        if !slots.new_contact[contact.slot] {
+            continue;
+        }
+        if zones.contains(contact.x, contact.y) {
+            log::debug!("Ignoring contact in slot {} (exclusion zone)", contact.slot);
+            slots.rejected[contact.slot] = true;
+        }
+    }
+}
+
+fn should_suppress_palm(palm: &Option<SharedPalmState>, palm_config: &PalmConfig) -> bool {
     let Some(palm_state) = palm else { return false };
     let Ok(state) = palm_state.lock() else { return false };
 
-    if state.pen_down {
-        return true;
+    state.pen_active(palm_config)
+}
+
+/// Mark contacts as palms if they are too large or lie next to the active pen.
+///
+/// Pen and contacts are compared on the device surface turned to
+/// `orientation`, rather than in output space, which `pen_transform` and
+/// `touch_transform` can make differ between the two.
+fn reject_palm_contacts(
+    slots: &mut SlotState,
+    palm: &Option<SharedPalmState>,
+    palm_config: &PalmConfig,
+    device: &DeviceProfile,
+    orientation: Orientation,
+) {
+    let pen_position = palm
+        .as_ref()
+        .and_then(|p| p.lock().ok())
+        .filter(|state| state.pen_active(palm_config))
+        .and_then(|state| state.pen_position);
+
+    // The pen's raw position, turned and scaled to touch units
+    let (pen_x_max, pen_y_max) = orientation.pen_output_dimensions(device.pen_x_max, device.pen_y_max);
+    let (out_x_max, out_y_max) = orientation.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+    let pen = pen_position.map(|(x, y)| {
+        let raw_x = (x * device.pen_x_max as f64) as i32;
+        let raw_y = (y * device.pen_y_max as f64) as i32;
+        let (px, py) = orientation.transform_pen(raw_x, raw_y, device.pen_x_max, device.pen_y_max);
+        (
+            (px as f64 / pen_x_max as f64 * out_x_max as f64) as i32,
+            (py as f64 / pen_y_max as f64 * out_y_max as f64) as i32,
+        )
+    });
+    let resolution = device.touch_resolution as f64;
+    let extent = (palm_config.region_mm * resolution) as i32;
+    let margin = (PALM_REGION_MARGIN_MM * resolution) as i32;
+
+    for contact in slots.output_contacts(device, CoordinateTransform::Orientation(orientation)) {
+        let too_large = palm_config
+            .touch_major_max
+            .zip(slots.shape[contact.slot].major)
+            .is_some_and(|(max, major)| major > max);
+        let near_pen = pen.is_some_and(|pen| {
+            in_palm_region(pen, (contact.x, contact.y), palm_config.handedness, extent, margin)
+        });
+
+        if too_large || near_pen {
+            log::debug!(
+                "Rejecting palm contact in slot {} ({})",
+                contact.slot,
+                if too_large { "size" } else { "near pen" }
+            );
+            slots.rejected[contact.slot] = true;
+        }
     }
+}
 
-    state
-        .last_pen_up
-        .map(|t| t.elapsed().as_millis() < grace_ms as u128)
-        .unwrap_or(false)
+/// Lift every contact and release anything held on the gesture and macro pad devices.
+fn release_all(
+    outputs: &mut TouchOutputs<impl EventSink>,
+    slots: &mut SlotState,
+) -> Result<()> {
+    emit_palm_suppression(outputs.sink, slots)?;
+    if let Some(gestures) = outputs.gestures.as_mut() {
+        gestures.cancel()?;
+    }
+    if let Some(macropad) = outputs.macropad.as_mut() {
+        for slot in 0..MT_SLOTS {
+            macropad.release(slot)?;
+        }
+    }
+    Ok(())
 }
 
 fn emit_palm_suppression(
-    uinput: &UinputDevice,
+    sink: &mut impl EventSink,
     slots: &mut SlotState,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut writer = uinput.writer();
+) -> Result<()> {
+    let mut events = Vec::new();
 
     for slot in 0..MT_SLOTS {
         if slots.tracking_id[slot].is_none() {
             continue;
         }
 
-        let slot_writer = writer.slot(Slot::from(slot as u16))?;
-        writer = slot_writer
-            .write(&[evdevil::event::AbsEvent::new(Abs::MT_TRACKING_ID, -1).into()])?
-            .finish_slot()?;
+        events.push(slot_event(slot));
+        events.push(evdevil::event::AbsEvent::new(Abs::MT_TRACKING_ID, -1).into());
         slots.tracking_id[slot] = None;
     }
 
-    let key_events = release_all_tool_keys();
-    writer = writer.write(&key_events)?;
-    writer.finish()?;
+    events.extend(release_all_tool_keys());
+    sink.write(&events)?;
 
     Ok(())
 }
 
+/// Select `slot` for the events that follow.
+fn slot_event(slot: usize) -> evdevil::event::InputEvent {
+    evdevil::event::AbsEvent::new(Abs::MT_SLOT, slot as i32).into()
+}
+
 fn emit_touch_frame(
-    uinput: &UinputDevice,
+    sink: &mut impl EventSink,
     slots: &mut SlotState,
     next_tracking_id: &mut i32,
     device: &DeviceProfile,
-    orientation: Orientation,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut writer = uinput.writer();
-    let contact_count = slots.active_count();
-    let (out_x_max, out_y_max) = orientation.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
+    transform: CoordinateTransform,
+) -> Result<()> {
+    let mut frame: Vec<evdevil::event::InputEvent> = Vec::with_capacity(64);
+    let contact_count = slots.forwarded_count();
+    let (out_x_max, out_y_max) = transform.touch_output_dimensions(device.touch_x_max, device.touch_y_max);
 
     for slot in 0..MT_SLOTS {
-        if slots.active[slot] {
+        if slots.forwarded(slot) {
             let is_new = slots.tracking_id[slot].is_none();
             if is_new {
                 *next_tracking_id = next_tracking_id.wrapping_add(1);
@@ -324,7 +726,7 @@ fn emit_touch_frame(
                 continue;
             };
 
-            let (out_x, out_y) = orientation.transform_touch(
+            let (out_x, out_y) = transform.transform_touch(
                 ax.clamp(0, device.touch_x_max),
                 ay.clamp(0, device.touch_y_max),
                 device.touch_x_max,
@@ -335,47 +737,62 @@ fn emit_touch_frame(
             slots.last_x[slot] = Some(ax);
             slots.last_y[slot] = Some(ay);
 
-            let slot_writer = writer.slot(Slot::from(slot as u16))?;
+            let mut events: Vec<evdevil::event::InputEvent> = Vec::with_capacity(8);
             if is_new {
                 let id = slots.tracking_id[slot].unwrap();
-                writer = slot_writer
-                    .write(&[
-                        evdevil::event::AbsEvent::new(Abs::MT_TRACKING_ID, id).into(),
-                        evdevil::event::AbsEvent::new(Abs::MT_POSITION_X, out_x).into(),
-                        evdevil::event::AbsEvent::new(Abs::MT_POSITION_Y, out_y).into(),
-                    ])?
-                    .finish_slot()?;
-            } else {
-                writer = slot_writer
-                    .write(&[
-                        evdevil::event::AbsEvent::new(Abs::MT_POSITION_X, out_x).into(),
-                        evdevil::event::AbsEvent::new(Abs::MT_POSITION_Y, out_y).into(),
-                    ])?
-                    .finish_slot()?;
+                events.push(evdevil::event::AbsEvent::new(Abs::MT_TRACKING_ID, id).into());
             }
+            events.push(evdevil::event::AbsEvent::new(Abs::MT_POSITION_X, out_x).into());
+            events.push(evdevil::event::AbsEvent::new(Abs::MT_POSITION_Y, out_y).into());
+            events.extend(shape_events(&slots.shape[slot], device, transform));
+
+            frame.push(slot_event(slot));
+            frame.extend(events);
         } else if slots.tracking_id[slot].is_some() {
-            let slot_writer = writer.slot(Slot::from(slot as u16))?;
-            writer = slot_writer
-                .write(&[evdevil::event::AbsEvent::new(Abs::MT_TRACKING_ID, -1).into()])?
-                .finish_slot()?;
+            frame.push(slot_event(slot));
+            frame.push(evdevil::event::AbsEvent::new(Abs::MT_TRACKING_ID, -1).into());
             slots.tracking_id[slot] = None;
         }
     }
 
-    if let Some((out_x, out_y)) = slots.get_primary_position(device, orientation) {
-        writer = writer.write(&[
-            evdevil::event::AbsEvent::new(Abs::X, out_x).into(),
-            evdevil::event::AbsEvent::new(Abs::Y, out_y).into(),
-        ])?;
+    if let Some((out_x, out_y)) = slots.get_primary_position(device, transform) {
+        frame.push(evdevil::event::AbsEvent::new(Abs::X, out_x).into());
+        frame.push(evdevil::event::AbsEvent::new(Abs::Y, out_y).into());
     }
 
-    let key_events = build_tool_key_events(contact_count);
-    writer = writer.write(&key_events)?;
-    writer.finish()?;
+    frame.extend(build_tool_key_events(contact_count));
+    sink.write(&frame)?;
 
     Ok(())
 }
 
+/// Size, angle, pressure and tool type events for a slot, in output orientation.
+///
+/// Values are re-sent every frame; the kernel drops the unchanged ones.
+fn shape_events(
+    shape: &ContactShape,
+    device: &DeviceProfile,
+    transform: CoordinateTransform,
+) -> Vec<evdevil::event::InputEvent> {
+    let (major, minor, angle) = transform.transform_touch_shape(
+        shape.major,
+        shape.minor,
+        shape.orientation,
+        device.touch_orientation_max,
+    );
+
+    [
+        (Abs::MT_TOUCH_MAJOR, major),
+        (Abs::MT_TOUCH_MINOR, minor),
+        (Abs::MT_ORIENTATION, angle),
+        (Abs::MT_PRESSURE, shape.pressure),
+        (Abs::MT_TOOL_TYPE, shape.tool_type),
+    ]
+    .into_iter()
+    .filter_map(|(abs, value)| value.map(|v| evdevil::event::AbsEvent::new(abs, v).into()))
+    .collect()
+}
+
 fn build_tool_key_events(contact_count: i32) -> Vec<evdevil::event::InputEvent> {
     let finger_down = contact_count > 0;
 
@@ -0,0 +1,103 @@
+//! Touch exclusion zones: areas where new contacts are ignored.
+//!
+//! Zones are given in output coordinates (after `Orientation`), so "left"
+//! always means the left edge as seen on the host.
+
+use serde::Deserialize;
+
+/// A rectangle in touch output coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// `[touch_exclusion]` section of the config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExclusionConfig {
+    /// Edge margins in millimetres.
+    #[serde(default)]
+    pub left_mm: f64,
+    #[serde(default)]
+    pub right_mm: f64,
+    #[serde(default)]
+    pub top_mm: f64,
+    #[serde(default)]
+    pub bottom_mm: f64,
+    /// Additional rectangles in output coordinates.
+    #[serde(default)]
+    pub rects: Vec<Rect>,
+}
+
+/// Exclusion zones resolved to output coordinates.
+#[derive(Debug, Clone, Default)]
+pub struct ExclusionZones {
+    rects: Vec<Rect>,
+}
+
+impl ExclusionZones {
+    /// Resolve edge margins for an output of `width`×`height` units at
+    /// `resolution` units per mm.
+    pub fn new(config: &ExclusionConfig, width: i32, height: i32, resolution: i32) -> Self {
+        let mm = |v: f64| (v * resolution as f64).round() as i32;
+        let (left, right, top, bottom) = (
+            mm(config.left_mm),
+            mm(config.right_mm),
+            mm(config.top_mm),
+            mm(config.bottom_mm),
+        );
+
+        let edges = [
+            Rect { x: 0, y: 0, width: left, height },
+            Rect { x: width - right, y: 0, width: right, height },
+            Rect { x: 0, y: 0, width, height: top },
+            Rect { x: 0, y: height - bottom, width, height: bottom },
+        ];
+
+        let rects = edges
+            .into_iter()
+            .filter(|r| r.width > 0 && r.height > 0)
+            .chain(config.rects.iter().copied())
+            .collect();
+
+        Self { rects }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        self.rects.iter().any(|r| r.contains(x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edge_margins() {
+        let config = ExclusionConfig {
+            right_mm: 5.0,
+            rects: vec![Rect { x: 100, y: 100, width: 10, height: 10 }],
+            ..Default::default()
+        };
+        let zones = ExclusionZones::new(&config, 1000, 800, 10);
+
+        assert!(zones.contains(960, 400));
+        assert!(!zones.contains(940, 400));
+        assert!(zones.contains(105, 105));
+        assert!(!zones.contains(0, 0));
+    }
+}
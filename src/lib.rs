@@ -0,0 +1,35 @@
+//! Forward reMarkable pen and touch input to virtual input devices on a
+//! Linux host.
+//!
+//! The `rm-pad` binary is a thin CLI over this crate. To embed the
+//! forwarding elsewhere, build a [`Pipeline`]; the pieces it is made of
+//! (device profiles, event parsing, the SSH stream) are public too.
+
+pub mod chord;
+pub mod config;
+pub mod control;
+#[cfg(feature = "dbus")]
+mod dbus;
+pub mod device;
+pub mod error;
+pub mod grab;
+pub mod input;
+mod metrics;
+pub mod orientation;
+pub mod palm;
+pub mod pipeline;
+pub mod recording;
+mod signals;
+pub mod ssh;
+pub mod state;
+pub mod systemd;
+pub mod transform;
+mod xochitl;
+
+pub use config::Config;
+pub use device::DeviceProfile;
+pub use error::{Error, Result};
+pub use input::{parse_event_time, parse_input_event};
+pub use orientation::Orientation;
+pub use pipeline::{Pipeline, PipelineBuilder};
+pub use ssh::open_input_stream;
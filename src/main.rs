@@ -1,206 +1,167 @@
-mod config;
-mod device;
+mod bench;
+mod cli;
+mod desktop;
+mod doctor;
 mod dump;
-mod grab;
-mod input;
-mod orientation;
-mod palm;
-mod ssh;
-
-use std::sync::atomic::Ordering;
-use std::sync::Arc;
+
 use std::thread;
 use std::time::Duration;
 
 use clap::Parser;
 
-use config::{Cli, Command, Config};
-use device::DeviceProfile;
-use palm::{PalmState, SharedPalmState};
+use rm_pad::config::Config;
+use rm_pad::device::DeviceProfile;
+use rm_pad::pipeline::{PipelineBuilder, RECONNECT_DELAY};
+use rm_pad::recording::{self, Recording};
+use rm_pad::{control, grab, ssh, systemd};
+
+use cli::{Cli, Command, TabletCommand};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
     
-    init_logging(cli.command.is_some());
-    
-    // Detect device via SSH (required)
-    let config_for_detection = Config::load(&cli, DeviceProfile::current());
-    let session = ssh::connect_for_detection(&config_for_detection)?;
-    let device = DeviceProfile::detect_via_ssh(&session)?;
-    log::info!("Using device profile: {}", device.name);
-    
-    let config = Config::load(&cli, device);
+    init_logging(!matches!(cli.command, None | Some(Command::Replay { .. })));
 
-    if let Some(command) = cli.command {
-        return run_subcommand(command, &config, device);
+    if let Some(command) = &cli.command {
+        return run_subcommand(&cli, command);
     }
 
+    let (config, device) = detect_device(&cli, true)?;
+
     if let Err(msg) = config.validate() {
         eprintln!("Error: {}", msg);
         eprintln!("\nRun with --help for usage information");
         std::process::exit(1);
     }
 
-    log_startup_info(&config);
-    run_input_forwarding(config, device)
-}
-
-fn init_logging(is_dump: bool) {
-    let default_level = if is_dump { "warn" } else { "info" };
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level)).init();
+    PipelineBuilder::from_config(config, device).build()?.run()?;
+    Ok(())
 }
 
-fn run_subcommand(
-    command: Command,
-    config: &Config,
-    device_profile: &'static DeviceProfile,
-) -> Result<()> {
-    match command {
-        Command::Dump { device } => match device.as_str() {
-            "touch" => dump::run_touch(config, device_profile),
-            "pen" => dump::run_pen(config, device_profile),
-            _ => {
-                eprintln!("Unknown dump device: {}. Use 'touch' or 'pen'.", device);
-                std::process::exit(1);
-            }
-        },
-    }
-}
+/// Detect the device via SSH and load the configuration for it. With `wait`,
+/// wait for the tablet if it isn't reachable yet.
+fn detect_device(cli: &Cli, wait: bool) -> Result<(Config, &'static DeviceProfile)> {
+    let config_for_detection = cli.load_config(DeviceProfile::current());
+    let session = connect_for_detection(&config_for_detection, wait)?;
+    let device = DeviceProfile::detect_via_ssh(&session)?;
+    log::info!("Using device profile: {}", device.name);
 
-fn log_startup_info(config: &Config) {
-    let palm_info = if config.no_palm_rejection {
-        "off".into()
-    } else {
-        format!("on (grace {}ms)", config.palm_grace_ms)
-    };
-
-    log::info!(
-        "Starting rm-pad: host={}, pen={}, touch={}, palm_rejection={}, grab_input={}, orientation={}",
-        config.host,
-        if config.run_pen() { &config.pen_device } else { "off" },
-        if config.run_touch() { &config.touch_device } else { "off" },
-        palm_info,
-        config.grab_input,
-        config.orientation
-    );
+    Ok((cli.load_config(device), device))
 }
 
-fn run_input_forwarding(config: Config, device: &'static DeviceProfile) -> Result<()> {
-    let palm_state = create_palm_state(&config);
-    let config = Arc::new(config);
-
-    // If grabbing, touch the watchdog file FIRST, then start watchdog thread
-    let watchdog_stop = if config.grab_input {
-        // Touch once before starting anything - this ensures the file exists
-        // and is fresh before any grabber starts
-        log::info!("Touching watchdog file before starting...");
-        if let Err(e) = ssh::touch_watchdog_once(&config) {
-            log::error!("Failed to touch watchdog: {}", e);
-            return Err(e);
+/// How long to keep trying to reach a tablet that was just plugged in.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Exit status when the tablet doesn't answer after rm-pad was started for
+/// it (`EX_UNAVAILABLE`). The udev-started unit doesn't restart on it.
+const EXIT_NO_TABLET: i32 = 69;
+
+/// Connect for device detection. With `exit_on_disconnect` rm-pad is usually
+/// started as soon as the USB network appears, before the tablet answers, so
+/// keep retrying for a while. Otherwise, with `wait`, wait for the tablet to
+/// be plugged in, keeping systemd informed in the meantime.
+fn connect_for_detection(config: &Config, wait: bool) -> Result<ssh2::Session> {
+    let deadline = std::time::Instant::now() + STARTUP_TIMEOUT;
+    let notifier = systemd::Notifier::from_env();
+    let mut waiting = false;
+    loop {
+        match ssh::connect_for_detection(config) {
+            Ok(session) => return Ok(session),
+            Err(e) if config.exit_on_disconnect && std::time::Instant::now() < deadline => {
+                log::debug!("Tablet not reachable yet: {}", e);
+                thread::sleep(RECONNECT_DELAY);
+            }
+            Err(e) if config.exit_on_disconnect && e.is_unreachable() => {
+                log::error!("Tablet not reachable at {}: {}", config.host, e);
+                std::process::exit(EXIT_NO_TABLET);
+            }
+            Err(e) if wait && e.is_unreachable() => {
+                let mut message = Vec::new();
+                if !waiting {
+                    log::info!("Tablet not reachable at {} ({}), waiting for it", config.host, e);
+                    message.push("READY=1".to_string());
+                    message.push(format!("STATUS=Waiting for tablet at {}", config.host));
+                    waiting = true;
+                }
+                if let Some(notifier) = &notifier {
+                    if notifier.watchdog().is_some() {
+                        message.push("WATCHDOG=1".to_string());
+                    }
+                    if !message.is_empty() {
+                        if let Err(e) = notifier.notify(&message.join("\n")) {
+                            log::debug!("sd_notify failed: {}", e);
+                        }
+                    }
+                }
+                thread::sleep(RECONNECT_DELAY);
+            }
+            Err(e) => return Err(e.into()),
         }
-
-        // Now start the background watchdog thread
-        Some(ssh::spawn_watchdog(&config))
-    } else {
-        None
-    };
-
-    let pen_handle = spawn_pen_thread(&config, device, &palm_state);
-    let touch_handle = spawn_touch_thread(&config, device, &palm_state);
-
-    let result = join_threads(pen_handle, touch_handle);
-
-    // Stop watchdog thread
-    if let Some(stop_flag) = watchdog_stop {
-        stop_flag.store(true, Ordering::Relaxed);
     }
-
-    result
 }
 
-fn create_palm_state(config: &Config) -> Option<SharedPalmState> {
-    if config.no_palm_rejection {
-        return None;
-    }
-    if !config.run_pen() || !config.run_touch() {
-        return None;
-    }
-
-    Some(Arc::new(std::sync::Mutex::new(PalmState::new())))
-}
-
-fn spawn_pen_thread(
-    config: &Arc<Config>,
-    device: &'static DeviceProfile,
-    palm_state: &Option<SharedPalmState>,
-) -> Option<thread::JoinHandle<()>> {
-    if !config.run_pen() {
-        return None;
-    }
-
-    let config = config.clone();
-    let palm = palm_state.clone();
-
-    Some(thread::spawn(move || {
-        run_with_reconnect("pen", || {
-            input::run_pen(&config, device, palm.clone())
-        });
-    }))
-}
-
-fn spawn_touch_thread(
-    config: &Arc<Config>,
-    device: &'static DeviceProfile,
-    palm_state: &Option<SharedPalmState>,
-) -> Option<thread::JoinHandle<()>> {
-    if !config.run_touch() {
-        return None;
-    }
-
-    let config = config.clone();
-    let palm = palm_state.clone();
-
-    Some(thread::spawn(move || {
-        run_with_reconnect("touch", || {
-            input::run_touch(&config, device, palm.clone())
-        });
-    }))
+fn init_logging(is_dump: bool) {
+    let default_level = if is_dump { "warn" } else { "info" };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level)).init();
 }
 
-/// Delay between reconnection attempts.
-const RECONNECT_DELAY: Duration = Duration::from_secs(2);
-
-fn run_with_reconnect<F>(name: &str, mut run_fn: F)
-where
-    F: FnMut() -> Result<()>,
-{
-    loop {
-        log::info!("[{}] Connecting", name);
+/// Lines of history shown by `rm-pad tablet logs --follow`.
+const TABLET_LOG_LINES: usize = 20;
 
-        if let Err(e) = run_fn() {
-            log::error!("[{}] Error: {}", name, e);
+fn run_subcommand(cli: &Cli, command: &Command) -> Result<()> {
+    match command {
+        // Talks to a running instance, no tablet connection needed
+        Command::Ctl { request } => Ok(control::run_client(&request.clone().into())?),
+        // Watches a running instance, no tablet connection needed
+        &Command::Dump { device, format, frames, decoded, output: true } => {
+            let options = dump::DumpOptions { source: device, format, frames, decoded };
+            dump::run_output(&options)
+        }
+        // Runs its own step-by-step connection checks
+        Command::Doctor => doctor::run(cli),
+        // Plays back a recording instead of connecting
+        Command::Replay { file, speed } => run_replay(cli, file, *speed),
+        // Only removes local files
+        Command::UninstallDesktopIntegration { prefix } => desktop::uninstall(prefix),
+        &Command::Dump { device, format, frames, decoded, output: false } => {
+            let (config, device_profile) = detect_device(cli, false)?;
+            let options = dump::DumpOptions { source: device, format, frames, decoded };
+            dump::run(&config, device_profile, &options)
+        }
+        Command::Bench { duration, json } => {
+            let (config, device_profile) = detect_device(cli, false)?;
+            bench::run(&config, device_profile, Duration::from_secs(*duration), *json)
+        }
+        Command::Record { file } => {
+            let (config, device_profile) = detect_device(cli, false)?;
+            Ok(recording::record(&config, device_profile, file)?)
+        }
+        Command::InstallDesktopIntegration { prefix } => {
+            let (_, device_profile) = detect_device(cli, false)?;
+            desktop::install(device_profile, prefix)
+        }
+        Command::Tablet { command: TabletCommand::Clean } => {
+            let (config, _) = detect_device(cli, false)?;
+            let session = ssh::connect_for_detection(&config)?;
+            Ok(grab::clean(&session)?)
+        }
+        Command::Tablet { command: TabletCommand::Logs { follow } } => {
+            let (config, _) = detect_device(cli, false)?;
+            let command = grab::log_command(*follow, TABLET_LOG_LINES);
+            Ok(ssh::stream_command(&config.host, &config.auth(), &command, |line| println!("{}", line))?)
         }
-
-        log::warn!(
-            "[{}] Disconnected, reconnecting in {}s",
-            name,
-            RECONNECT_DELAY.as_secs()
-        );
-        thread::sleep(RECONNECT_DELAY);
     }
 }
 
-fn join_threads(
-    pen: Option<thread::JoinHandle<()>>,
-    touch: Option<thread::JoinHandle<()>>,
-) -> Result<()> {
-    if let Some(h) = pen {
-        h.join().unwrap();
-    }
-    if let Some(h) = touch {
-        h.join().unwrap();
-    }
+/// Feed a recording through the forwarding pipeline instead of the tablet.
+fn run_replay(cli: &Cli, path: &std::path::Path, speed: f64) -> Result<()> {
+    let recording = Recording::load(path)?;
+    let device = recording.device()?;
+    let config = cli.load_config(device);
+
+    log::info!("Replaying {} ({} events from {})", path.display(), recording.entries.len(), device.name);
+    recording::replay(&recording, config, speed)?;
     Ok(())
 }
@@ -0,0 +1,143 @@
+//! Prometheus metrics for long-running instances.
+//!
+//! With `metrics_port` set, `http://127.0.0.1:<port>/metrics` serves the
+//! runtime counters in the Prometheus text format.
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::state::{InputKind, InputStatus, SharedState, Status};
+
+/// How long a client gets to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serve `/metrics` on localhost for the lifetime of the process.
+pub fn spawn_metrics_server(port: u16, state: &SharedState) -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    log::info!("Metrics available at http://127.0.0.1:{}/metrics", port);
+
+    let state = state.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = serve_client(stream, &state) {
+                        log::debug!("Metrics client error: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("Metrics accept failed: {}", e),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn serve_client(stream: TcpStream, state: &SharedState) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), Some("/metrics")) => {
+            ("200 OK", "text/plain; version=0.0.4", render(&state.status()))
+        }
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+/// Format a status snapshot as Prometheus metrics.
+fn render(status: &Status) -> String {
+    let inputs = [(InputKind::Pen, &status.pen), (InputKind::Touch, &status.touch)];
+    let mut out = String::new();
+
+    let per_input = |out: &mut String, name: &str, kind: &str, help: &str, value: fn(&InputStatus) -> Option<f64>| {
+        let _ = writeln!(out, "# HELP rm_pad_{} {}", name, help);
+        let _ = writeln!(out, "# TYPE rm_pad_{} {}", name, kind);
+        for (input, input_status) in inputs {
+            if let Some(value) = value(input_status) {
+                let _ = writeln!(out, "rm_pad_{}{{input=\"{}\"}} {}", name, input, value);
+            }
+        }
+    };
+
+    per_input(&mut out, "frames_forwarded_total", "counter", "Frames written to the virtual device.", |s| {
+        Some(s.frames as f64)
+    });
+    per_input(&mut out, "palm_suppressed_frames_total", "counter", "Frames dropped by palm rejection.", |s| {
+        Some(s.palm_suppressed as f64)
+    });
+    per_input(&mut out, "reconnects_total", "counter", "Times the input stream was reopened.", |s| {
+        Some(s.reconnects as f64)
+    });
+    per_input(&mut out, "received_bytes_total", "counter", "Raw event bytes received from the tablet.", |s| {
+        Some(s.bytes as f64)
+    });
+    per_input(&mut out, "parse_failures_total", "counter", "Raw events that couldn't be parsed.", |s| {
+        Some(s.parse_failures as f64)
+    });
+    per_input(&mut out, "contacts", "gauge", "Contacts currently forwarded.", |s| Some(s.contacts as f64));
+    per_input(&mut out, "connected", "gauge", "Whether the input stream is connected.", |s| {
+        Some(s.connected as u8 as f64)
+    });
+    per_input(&mut out, "last_event_age_seconds", "gauge", "Time since the last event from the tablet.", |s| {
+        s.last_event_age_ms.map(|ms| ms as f64 / 1000.0)
+    });
+
+    let _ = writeln!(out, "# HELP rm_pad_paused Whether forwarding is paused.");
+    let _ = writeln!(out, "# TYPE rm_pad_paused gauge");
+    let _ = writeln!(out, "rm_pad_paused {}", status.paused as u8);
+    let _ = writeln!(out, "# HELP rm_pad_grabbed Whether the tablet input is grabbed.");
+    let _ = writeln!(out, "# TYPE rm_pad_grabbed gauge");
+    let _ = writeln!(out, "rm_pad_grabbed {}", status.grab as u8);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orientation::Orientation;
+
+    #[test]
+    fn test_render() {
+        let status = Status {
+            model: "reMarkable 2".into(),
+            orientation: Orientation::default(),
+            paused: false,
+            grab: true,
+            pen: InputStatus { frames: 42, last_event_age_ms: Some(1500), ..Default::default() },
+            touch: InputStatus { connected: true, contacts: 2, ..Default::default() },
+            palm: None,
+        };
+        let text = render(&status);
+
+        assert!(text.contains("# TYPE rm_pad_frames_forwarded_total counter\n"));
+        assert!(text.contains("rm_pad_frames_forwarded_total{input=\"pen\"} 42\n"));
+        assert!(text.contains("rm_pad_contacts{input=\"touch\"} 2\n"));
+        assert!(text.contains("rm_pad_last_event_age_seconds{input=\"pen\"} 1.5\n"));
+        assert!(!text.contains("rm_pad_last_event_age_seconds{input=\"touch\"}"));
+        assert!(text.contains("rm_pad_grabbed 1\n"));
+    }
+}
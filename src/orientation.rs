@@ -1,11 +1,11 @@
 //! Screen orientation handling for input coordinate transforms.
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
 
 /// Screen orientation relative to the default portrait mode (buttons at top).
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum Orientation {
     /// Portrait mode (buttons at top, no rotation).
@@ -20,6 +20,26 @@ pub enum Orientation {
 }
 
 impl Orientation {
+    /// The orientation after turning the tablet a quarter turn clockwise.
+    pub fn rotated_clockwise(&self) -> Self {
+        match self {
+            Orientation::Portrait => Orientation::LandscapeRight,
+            Orientation::LandscapeRight => Orientation::Inverted,
+            Orientation::Inverted => Orientation::LandscapeLeft,
+            Orientation::LandscapeLeft => Orientation::Portrait,
+        }
+    }
+
+    /// The orientation after turning the tablet a quarter turn counter-clockwise.
+    pub fn rotated_counter_clockwise(&self) -> Self {
+        match self {
+            Orientation::Portrait => Orientation::LandscapeLeft,
+            Orientation::LandscapeLeft => Orientation::Inverted,
+            Orientation::Inverted => Orientation::LandscapeRight,
+            Orientation::LandscapeRight => Orientation::Portrait,
+        }
+    }
+
     /// Transform touch coordinates from device space to output space.
     /// Touch is natively portrait-oriented but with Y=0 at bottom.
     pub fn transform_touch(&self, x: i32, y: i32, x_max: i32, y_max: i32) -> (i32, i32) {
@@ -65,6 +85,35 @@ impl Orientation {
         }
     }
 
+    /// Transform a touch contact's shape (major/minor axis and ellipse angle).
+    ///
+    /// The angle is a signed quarter turn from the Y axis in `-angle_max..=angle_max`.
+    /// Mirroring one axis negates it; swapping X/Y turns it by a quarter.
+    /// When the device doesn't report an angle, major and minor are taken as
+    /// Y and X extents and are swapped along with the axes instead.
+    pub fn transform_touch_shape(
+        &self,
+        major: Option<i32>,
+        minor: Option<i32>,
+        angle: Option<i32>,
+        angle_max: i32,
+    ) -> (Option<i32>, Option<i32>, Option<i32>) {
+        let swaps_axes = matches!(self, Orientation::LandscapeRight | Orientation::LandscapeLeft);
+
+        match (angle, swaps_axes) {
+            (None, true) => (minor, major, None),
+            (None, false) => (major, minor, None),
+            // LandscapeRight/Left: quarter turn, wrapped back into range
+            (Some(a), true) => {
+                let turned = angle_max - a;
+                let wrapped = if turned > angle_max { turned - 2 * angle_max } else { turned };
+                (major, minor, Some(wrapped))
+            }
+            // Portrait/Inverted: a single mirrored axis
+            (Some(a), false) => (major, minor, Some(-a)),
+        }
+    }
+
     /// Get output dimensions for touch after rotation.
     /// Touch is natively portrait-oriented.
     pub fn touch_output_dimensions(&self, x_max: i32, y_max: i32) -> (i32, i32) {
@@ -136,6 +185,29 @@ mod tests {
         assert_eq!(landscape.touch_output_dimensions(100, 200), (200, 100));
     }
 
+    #[test]
+    fn test_touch_shape_transform() {
+        let (major, minor) = (Some(30), Some(10));
+        // Without an angle, quarter turns swap the extents
+        assert_eq!(
+            Orientation::LandscapeRight.transform_touch_shape(major, minor, None, 127),
+            (minor, major, None)
+        );
+        // With an angle, the ellipse is turned instead
+        assert_eq!(
+            Orientation::LandscapeRight.transform_touch_shape(major, minor, Some(0), 127),
+            (major, minor, Some(127))
+        );
+        assert_eq!(
+            Orientation::LandscapeLeft.transform_touch_shape(major, minor, Some(-100), 127),
+            (major, minor, Some(-27))
+        );
+        assert_eq!(
+            Orientation::Portrait.transform_touch_shape(major, minor, Some(40), 127),
+            (major, minor, Some(-40))
+        );
+    }
+
     #[test]
     fn test_from_str() {
         assert_eq!("portrait".parse::<Orientation>().unwrap(), Orientation::Portrait);
@@ -1,17 +1,173 @@
+use std::fmt;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::config::Config;
 
 /// Shared state for palm rejection between pen and touch threads.
 #[derive(Default)]
 pub struct PalmState {
     pub pen_down: bool,
     pub last_pen_up: Option<Instant>,
+    /// Pen position in normalized raw device coordinates (0.0..=1.0) while
+    /// in range, independent of the orientation and `pen_transform`.
+    pub pen_position: Option<(f64, f64)>,
+    /// Pen is within the digitizer's sensing range (BTN_TOOL_PEN held).
+    pub pen_in_range: bool,
+    /// Last hover distance (ABS_DISTANCE) while in range.
+    pub pen_distance: Option<i32>,
 }
 
 impl PalmState {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Whether the pen is down, hovering within the configured distance, or
+    /// was lifted less than the grace period ago.
+    pub fn pen_active(&self, config: &PalmConfig) -> bool {
+        self.pen_down
+            || self.pen_hovering(config.hover_distance)
+            || self.last_pen_up.is_some_and(|t| t.elapsed() < config.grace)
+    }
+
+    fn pen_hovering(&self, threshold: Option<i32>) -> bool {
+        let Some(threshold) = threshold else { return false };
+        self.pen_in_range && self.pen_distance.is_some_and(|d| d <= threshold)
+    }
 }
 
 pub type SharedPalmState = Arc<Mutex<PalmState>>;
+
+/// State shared by the pen and touch threads, or `None` when palm rejection
+/// is off or only one of them runs.
+pub fn create_shared_state(config: &Config) -> Option<SharedPalmState> {
+    if config.no_palm_rejection {
+        return None;
+    }
+    if !config.run_pen() || !config.run_touch() {
+        return None;
+    }
+
+    Some(Arc::new(Mutex::new(PalmState::new())))
+}
+
+/// How touches are rejected while the pen is in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PalmMode {
+    /// Suppress all touch input while the pen is active.
+    #[default]
+    Global,
+    /// Reject individual contacts that are large or near the pen.
+    Smart,
+}
+
+impl fmt::Display for PalmMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PalmMode::Global => write!(f, "global"),
+            PalmMode::Smart => write!(f, "smart"),
+        }
+    }
+}
+
+impl FromStr for PalmMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "global" => Ok(PalmMode::Global),
+            "smart" => Ok(PalmMode::Smart),
+            _ => Err(format!("Invalid palm mode '{}'. Valid values: global, smart", s)),
+        }
+    }
+}
+
+/// Which hand holds the pen; the palm rests on the opposite side of the tip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Handedness {
+    #[default]
+    Right,
+    Left,
+}
+
+impl fmt::Display for Handedness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Handedness::Right => write!(f, "right"),
+            Handedness::Left => write!(f, "left"),
+        }
+    }
+}
+
+impl FromStr for Handedness {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "right" => Ok(Handedness::Right),
+            "left" => Ok(Handedness::Left),
+            _ => Err(format!("Invalid handedness '{}'. Valid values: right, left", s)),
+        }
+    }
+}
+
+/// Palm rejection settings used by the touch thread.
+#[derive(Debug, Clone)]
+pub struct PalmConfig {
+    pub mode: PalmMode,
+    pub grace: Duration,
+    pub handedness: Handedness,
+    /// Contacts with a larger ABS_MT_TOUCH_MAJOR are palms (smart mode).
+    pub touch_major_max: Option<i32>,
+    /// Size of the area next to the pen treated as palm (smart mode).
+    pub region_mm: f64,
+    /// Hover distance (raw ABS_DISTANCE) below which a pen in range counts as active.
+    pub hover_distance: Option<i32>,
+}
+
+/// Distance (mm) the palm region reaches behind the pen tip.
+pub const PALM_REGION_MARGIN_MM: f64 = 15.0;
+
+/// Whether `touch` lies where the writing hand rests around a pen at `pen`.
+///
+/// Coordinates are in the current orientation (X right, Y down). The region starts
+/// `margin` behind the pen tip and reaches `extent` below it and towards the
+/// writing hand's side.
+pub fn in_palm_region(
+    pen: (i32, i32),
+    touch: (i32, i32),
+    handedness: Handedness,
+    extent: i32,
+    margin: i32,
+) -> bool {
+    let dx = touch.0 - pen.0;
+    let dx = match handedness {
+        Handedness::Right => dx,
+        Handedness::Left => -dx,
+    };
+    let dy = touch.1 - pen.1;
+
+    (-margin..=extent).contains(&dx) && (-margin..=extent).contains(&dy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_palm_region_follows_handedness() {
+        let pen = (500, 500);
+        // Below and right of the tip is the right hand's palm
+        assert!(in_palm_region(pen, (600, 650), Handedness::Right, 300, 50));
+        assert!(!in_palm_region(pen, (600, 650), Handedness::Left, 300, 50));
+        assert!(in_palm_region(pen, (400, 650), Handedness::Left, 300, 50));
+        // Well above the pen (the off hand panning) gets through
+        assert!(!in_palm_region(pen, (600, 100), Handedness::Right, 300, 50));
+    }
+}
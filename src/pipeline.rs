@@ -0,0 +1,434 @@
+//! The forwarding pipeline: pen and touch threads that stream from the
+//! tablet into virtual devices, plus the services around them (watchdog,
+//! control socket, metrics, systemd notifications).
+//!
+//! ```no_run
+//! use rm_pad::Pipeline;
+//!
+//! let pipeline = Pipeline::builder("10.11.99.1")
+//!     .password("secret")
+//!     .configure(|config| config.gestures.enabled = true)
+//!     .build()?;
+//! pipeline.run()?;
+//! # Ok::<(), rm_pad::Error>(())
+//! ```
+
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::control;
+use crate::device::DeviceProfile;
+use crate::error::{Error, Result};
+use crate::input;
+use crate::metrics;
+use crate::orientation::Orientation;
+use crate::palm::{self, SharedPalmState};
+use crate::signals;
+use crate::ssh;
+use crate::state::{InputKind, RuntimeState, SharedState};
+use crate::systemd;
+use crate::xochitl;
+
+/// Delay between reconnection attempts.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// How long to wait for the forwarding threads when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often the main loop reports to systemd.
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct PipelineBuilder {
+    config: Config,
+    device: Option<&'static DeviceProfile>,
+    /// Whether device paths still at the defaults follow the detected model.
+    default_device_paths: bool,
+    control: bool,
+    handle_signals: bool,
+}
+
+impl PipelineBuilder {
+    /// Build from a complete configuration for a known device, e.g. one
+    /// merged from the configuration file and command line.
+    pub fn from_config(config: Config, device: &'static DeviceProfile) -> Self {
+        Self {
+            config,
+            device: Some(device),
+            default_device_paths: false,
+            control: true,
+            handle_signals: true,
+        }
+    }
+
+    /// Use `device` instead of detecting the model over SSH.
+    pub fn device(mut self, device: &'static DeviceProfile) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    /// Authenticate with `password` instead of a key.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.config.password = Some(password.into());
+        self
+    }
+
+    /// Authenticate with the private key at `path`.
+    pub fn key_path(mut self, path: impl Into<String>) -> Self {
+        self.config.key_path = Some(path.into());
+        self
+    }
+
+    /// Grab the tablet input so the tablet UI doesn't see it.
+    pub fn grab_input(mut self, grab: bool) -> Self {
+        self.config.grab_input = grab;
+        self
+    }
+
+    pub fn orientation(mut self, orientation: Orientation) -> Self {
+        self.config.orientation = orientation;
+        self
+    }
+
+    /// Change any other setting. Device paths left alone here follow the
+    /// detected model.
+    pub fn configure(mut self, f: impl FnOnce(&mut Config)) -> Self {
+        f(&mut self.config);
+        self
+    }
+
+    /// Serve the control socket and, with the `dbus` feature, the D-Bus
+    /// interface. On by default.
+    pub fn control(mut self, enabled: bool) -> Self {
+        self.control = enabled;
+        self
+    }
+
+    /// Stop on SIGINT and SIGTERM. On by default; without it, stop the
+    /// pipeline through [`RuntimeState::request_shutdown`].
+    pub fn handle_signals(mut self, enabled: bool) -> Self {
+        self.handle_signals = enabled;
+        self
+    }
+
+    /// Check the configuration and detect the tablet model unless it was
+    /// given.
+    pub fn build(mut self) -> Result<Pipeline> {
+        self.config.validate().map_err(Error::InvalidConfig)?;
+
+        let device = match self.device {
+            Some(device) => device,
+            None => DeviceProfile::detect_via_ssh(&ssh::connect_for_detection(&self.config)?)?,
+        };
+        if self.default_device_paths {
+            let placeholder = DeviceProfile::current();
+            if self.config.pen_device == placeholder.pen_device {
+                self.config.pen_device = device.pen_device.into();
+            }
+            if self.config.touch_device == placeholder.touch_device {
+                self.config.touch_device = device.touch_device.into();
+            }
+        }
+
+        let palm_state = palm::create_shared_state(&self.config);
+        let state = Arc::new(RuntimeState::new(&self.config, device, palm_state.clone()));
+        Ok(Pipeline {
+            config: Arc::new(self.config),
+            device,
+            state,
+            palm_state,
+            control: self.control,
+            handle_signals: self.handle_signals,
+        })
+    }
+}
+
+/// Forwards the tablet input until stopped or, with `exit_on_disconnect`,
+/// until the tablet goes away.
+pub struct Pipeline {
+    config: Arc<Config>,
+    device: &'static DeviceProfile,
+    state: SharedState,
+    palm_state: Option<SharedPalmState>,
+    control: bool,
+    handle_signals: bool,
+}
+
+impl Pipeline {
+    /// Start from the defaults for the tablet at `host`.
+    pub fn builder(host: impl Into<String>) -> PipelineBuilder {
+        let mut config = Config::new(DeviceProfile::current());
+        config.host = host.into();
+        PipelineBuilder {
+            config,
+            device: None,
+            default_device_paths: true,
+            control: true,
+            handle_signals: true,
+        }
+    }
+
+    pub fn device(&self) -> &'static DeviceProfile {
+        self.device
+    }
+
+    /// Handle to pause, rotate or stop the pipeline from another thread.
+    pub fn state(&self) -> SharedState {
+        self.state.clone()
+    }
+
+    /// Forward input until the pipeline is stopped.
+    pub fn run(self) -> Result<()> {
+        let Pipeline { config, device, state, palm_state, .. } = &self;
+        log_startup_info(config);
+
+        if self.handle_signals {
+            signals::spawn_signal_handler(state)?;
+        }
+        let mut control_socket = None;
+        if self.control {
+            // Not worth refusing to forward over
+            match control::spawn_control_server(state) {
+                Ok(path) => control_socket = Some(path),
+                Err(e) => log::warn!("Control socket unavailable: {}", e),
+            }
+            #[cfg(feature = "dbus")]
+            if let Err(e) = crate::dbus::spawn_dbus_service(state) {
+                log::warn!("D-Bus interface unavailable: {}", e);
+            }
+        }
+        if let Some(port) = config.metrics_port {
+            metrics::spawn_metrics_server(port, state).map_err(|e| {
+                std::io::Error::new(e.kind(), format!("Cannot serve metrics on port {}: {}", port, e))
+            })?;
+        }
+
+        // If grabbing, touch the watchdog file FIRST, then start watchdog thread
+        if config.grab_input {
+            // Touch once before starting anything - this ensures the file exists
+            // and is fresh before any grabber starts
+            log::info!("Touching watchdog file before starting...");
+            if let Err(e) = ssh::touch_watchdog_once(config) {
+                log::error!("Failed to touch watchdog: {}", e);
+                return Err(e);
+            }
+        }
+
+        // Always started, since the input can be grabbed later through the
+        // control socket; it only touches the file while grabbing
+        let watchdog_stop = ssh::spawn_watchdog(config, state);
+        let log_tail_stop = ssh::spawn_log_tail(config);
+
+        let orientation_stop = config
+            .follow_tablet_orientation
+            .then(|| xochitl::spawn_orientation_watcher(config, state));
+
+        let pen_handle = spawn_pen_thread(config, device, state, palm_state);
+        let touch_handle = spawn_touch_thread(config, device, state, palm_state);
+
+        let handles = [(InputKind::Pen, pen_handle), (InputKind::Touch, touch_handle)]
+            .into_iter()
+            .filter_map(|(kind, handle)| Some((kind, handle?)))
+            .collect();
+        let result = supervise(config, state, handles);
+
+        // Stop watchdog, log tail and orientation threads
+        for stop_flag in [watchdog_stop, log_tail_stop].into_iter().chain(orientation_stop) {
+            stop_flag.store(true, Ordering::Relaxed);
+        }
+
+        if state.shutting_down() {
+            self.shut_down(control_socket);
+        }
+
+        result
+    }
+
+    /// Clean up after the forwarding threads have stopped.
+    fn shut_down(&self, control_socket: Option<PathBuf>) {
+        if let Some(path) = control_socket {
+            let _ = std::fs::remove_file(path);
+        }
+
+        // The grabber exits as soon as the watchdog file is gone
+        if self.config.clean_tablet_on_exit {
+            if let Err(e) = ssh::clean_tablet(&self.config) {
+                log::warn!("Failed to clean up the tablet: {}", e);
+            }
+        } else if self.config.grab_input || self.state.grab() {
+            if let Err(e) = ssh::remove_watchdog(&self.config) {
+                log::warn!("Failed to remove watchdog file: {}", e);
+            }
+        }
+        log::info!("Stopped");
+    }
+}
+
+fn log_startup_info(config: &Config) {
+    let palm_info = if config.no_palm_rejection {
+        "off".into()
+    } else {
+        format!("{} (grace {}ms)", config.palm_mode, config.palm_grace_ms)
+    };
+
+    log::info!(
+        "Starting rm-pad: host={}, pen={}, touch={}, touch_mode={}, palm_rejection={}, grab_input={}, orientation={}",
+        config.host,
+        if config.run_pen() { &config.pen_device } else { "off" },
+        if config.run_touch() { &config.touch_device } else { "off" },
+        config.touch_mode,
+        palm_info,
+        config.grab_input,
+        config.orientation
+    );
+}
+
+fn spawn_pen_thread(
+    config: &Arc<Config>,
+    device: &'static DeviceProfile,
+    state: &SharedState,
+    palm_state: &Option<SharedPalmState>,
+) -> Option<thread::JoinHandle<()>> {
+    if !config.run_pen() {
+        return None;
+    }
+
+    let config = config.clone();
+    let state = state.clone();
+    let palm = palm_state.clone();
+
+    Some(thread::spawn(move || {
+        run_with_reconnect(InputKind::Pen, &config, &state, || {
+            let result = input::run_pen(&config, device, &state, palm.clone());
+            state.set_connected(InputKind::Pen, false);
+            result
+        });
+    }))
+}
+
+fn spawn_touch_thread(
+    config: &Arc<Config>,
+    device: &'static DeviceProfile,
+    state: &SharedState,
+    palm_state: &Option<SharedPalmState>,
+) -> Option<thread::JoinHandle<()>> {
+    if !config.run_touch() {
+        return None;
+    }
+
+    let config = config.clone();
+    let state = state.clone();
+    let palm = palm_state.clone();
+
+    Some(thread::spawn(move || {
+        run_with_reconnect(InputKind::Touch, &config, &state, || {
+            let result = input::run_touch(&config, device, &state, palm.clone());
+            state.set_connected(InputKind::Touch, false);
+            result
+        });
+    }))
+}
+
+/// Run a forwarding function until it fails, then reconnect, or stop when
+/// shutting down or `exit_on_disconnect` is set.
+fn run_with_reconnect<F>(name: InputKind, config: &Config, state: &SharedState, mut run_fn: F)
+where
+    F: FnMut() -> Result<()>,
+{
+    while !state.shutting_down() {
+        log::info!("[{}] Connecting", name);
+        state.report_progress(name);
+
+        match run_fn() {
+            _ if state.shutting_down() => break,
+            // Stream closed on purpose, e.g. to grab or release the input
+            Ok(()) => continue,
+            Err(e) if config.exit_on_disconnect => {
+                log::info!("[{}] Disconnected ({}), exiting", name, e);
+                return;
+            }
+            Err(e) => log::error!("[{}] Error: {}", name, e),
+        }
+
+        log::warn!(
+            "[{}] Disconnected, reconnecting in {}s",
+            name,
+            RECONNECT_DELAY.as_secs()
+        );
+        state.report_progress(name);
+        thread::sleep(RECONNECT_DELAY);
+    }
+    log::debug!("[{}] Stopped", name);
+}
+
+/// Wait until a forwarding thread stops, keeping systemd informed with
+/// `READY=1`, `STATUS=` and watchdog pings in the meantime. Pings are only
+/// sent while every forwarding thread shows progress, so systemd restarts
+/// rm-pad when one hangs. A thread that panicked is an error, so that
+/// systemd restarts rm-pad for that too.
+fn supervise(config: &Config, state: &SharedState, handles: Vec<(InputKind, thread::JoinHandle<()>)>) -> Result<()> {
+    let notifier = systemd::Notifier::from_env();
+    let interval = notifier
+        .as_ref()
+        .and_then(|n| n.watchdog())
+        .map_or(SUPERVISE_INTERVAL, |timeout| (timeout / 2).min(SUPERVISE_INTERVAL));
+    let mut ready = false;
+    let mut last_status = String::new();
+
+    while !handles.iter().any(|(_, h)| h.is_finished()) && !state.shutting_down() {
+        if let Some(notifier) = &notifier {
+            let status = state.status();
+            let mut message = Vec::new();
+
+            let flowing = (!config.run_pen() || status.pen.connected)
+                && (!config.run_touch() || status.touch.connected);
+            if flowing && !ready {
+                message.push("READY=1".to_string());
+                ready = true;
+            }
+
+            let line = systemd::status_line(&status, config.run_pen(), config.run_touch());
+            if line != last_status {
+                message.push(format!("STATUS={}", line));
+                last_status = line;
+            }
+
+            if let Some(timeout) = notifier.watchdog() {
+                let alive = |kind, running: bool| !running || state.made_progress(kind, timeout);
+                if alive(InputKind::Pen, config.run_pen()) && alive(InputKind::Touch, config.run_touch()) {
+                    message.push("WATCHDOG=1".to_string());
+                }
+            }
+
+            if !message.is_empty() {
+                if let Err(e) = notifier.notify(&message.join("\n")) {
+                    log::debug!("sd_notify failed: {}", e);
+                }
+            }
+        }
+
+        thread::sleep(interval);
+    }
+
+    if let Some(notifier) = &notifier {
+        let _ = notifier.notify("STOPPING=1");
+    }
+
+    // Give the threads a moment to release their devices
+    if state.shutting_down() {
+        let deadline = std::time::Instant::now() + SHUTDOWN_TIMEOUT;
+        while !handles.iter().all(|(_, h)| h.is_finished()) && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    for (kind, handle) in handles {
+        if handle.is_finished() && handle.join().is_err() {
+            return Err(Error::ThreadPanicked(kind.to_string()));
+        }
+    }
+    Ok(())
+}
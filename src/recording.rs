@@ -0,0 +1,350 @@
+//! Recordings of raw tablet input, for `rm-pad record` and `rm-pad replay`.
+//!
+//! A recording is a JSON-lines file: a header naming the device profile,
+//! then one line per raw `input_event` with the time it arrived (in
+//! microseconds since the recording started), the input it came from and
+//! its bytes in hex, e.g. `{"t":1520,"input":"pen","data":"8d3f..."}`.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::error::{Error, Result};
+use crate::input::{self, VirtualDevice};
+use crate::palm;
+use crate::signals;
+use crate::ssh;
+use crate::state::{InputKind, RuntimeState, SharedState};
+
+const FORMAT_VERSION: u32 = 1;
+
+/// How often a replay checks for shutdown while waiting for the next event.
+const REPLAY_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    /// Device profile name, e.g. "reMarkable 2".
+    pub model: String,
+    pub input_event_size: usize,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Microseconds since the recording started.
+    pub t: u64,
+    pub input: InputKind,
+    #[serde(with = "hex")]
+    pub data: Vec<u8>,
+}
+
+pub struct Recording {
+    pub header: Header,
+    pub entries: Vec<Entry>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .map_err(|e| Error::InvalidRecording(format!("Cannot open {}: {}", path.display(), e)))?;
+        Self::read(BufReader::new(file))
+    }
+
+    pub fn read(reader: impl BufRead) -> Result<Self> {
+        let mut lines = reader.lines();
+        let first = lines.next().ok_or_else(|| Error::InvalidRecording("Recording is empty".into()))?;
+        let header: Header = serde_json::from_str(&first?)?;
+        if header.version != FORMAT_VERSION {
+            return Err(Error::InvalidRecording(format!("Unsupported version {}", header.version)));
+        }
+
+        let mut entries = Vec::new();
+        for (number, line) in lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line)
+                .map_err(|e| Error::InvalidRecording(format!("Line {}: {}", number + 2, e)))?;
+            entries.push(entry);
+        }
+
+        Ok(Self { header, entries })
+    }
+
+    /// The profile of the device the recording was made on.
+    pub fn device(&self) -> Result<&'static DeviceProfile> {
+        let device = DeviceProfile::by_name(&self.header.model).ok_or_else(|| {
+            Error::InvalidRecording(format!("Unknown device model '{}'", self.header.model))
+        })?;
+        if self.header.input_event_size != device.input_event_size {
+            return Err(Error::InvalidRecording(format!(
+                "{}-byte events, {} uses {}",
+                self.header.input_event_size, device.name, device.input_event_size
+            )));
+        }
+        Ok(device)
+    }
+
+    /// Whether the recording contains any events from `kind`.
+    pub fn has(&self, kind: InputKind) -> bool {
+        self.entries.iter().any(|e| e.input == kind)
+    }
+
+    /// Events from `kind` with their recorded times.
+    pub fn events(&self, kind: InputKind) -> VecDeque<(Duration, Vec<u8>)> {
+        self.entries
+            .iter()
+            .filter(|e| e.input == kind)
+            .map(|e| (Duration::from_micros(e.t), e.data.clone()))
+            .collect()
+    }
+}
+
+/// Timeline shared by the streams of one replay, started by the first read.
+pub struct ReplayClock {
+    start: OnceLock<Instant>,
+    /// Playback speed factor; 0 replays as fast as possible.
+    speed: f64,
+    state: SharedState,
+}
+
+impl ReplayClock {
+    pub fn new(speed: f64, state: &SharedState) -> Arc<Self> {
+        Arc::new(Self { start: OnceLock::new(), speed, state: state.clone() })
+    }
+
+    /// Sleep until `t` into the recording. Fails once shutting down.
+    fn wait_until(&self, t: Duration) -> io::Result<()> {
+        let start = *self.start.get_or_init(Instant::now);
+        let target = if self.speed > 0.0 { start + t.div_f64(self.speed) } else { start };
+
+        loop {
+            if self.state.shutting_down() {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "Replay stopped"));
+            }
+            let remaining = target.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            thread::sleep(remaining.min(REPLAY_POLL));
+        }
+    }
+}
+
+/// Raw events of one input, handed out at their recorded times. Reads
+/// return end-of-file after the last event.
+pub struct ReplayStream {
+    events: VecDeque<(Duration, Vec<u8>)>,
+    clock: Arc<ReplayClock>,
+    current: VecDeque<u8>,
+}
+
+impl ReplayStream {
+    pub fn new(events: VecDeque<(Duration, Vec<u8>)>, clock: &Arc<ReplayClock>) -> Self {
+        Self { events, clock: clock.clone(), current: VecDeque::new() }
+    }
+}
+
+impl Read for ReplayStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.current.is_empty() {
+            let Some((t, data)) = self.events.pop_front() else {
+                return Ok(0);
+            };
+            self.clock.wait_until(t)?;
+            self.current = data.into();
+        }
+        self.current.read(buf)
+    }
+}
+
+/// Feed a recording through the forwarding pipeline, pacing events as they
+/// were recorded, `speed` times as fast; 0 replays as fast as possible.
+/// Stops at the end of the recording or on Ctrl+C, and fails with the first
+/// error of either input.
+pub fn replay(recording: &Recording, config: Config, speed: f64) -> Result<()> {
+    if speed.is_nan() || speed < 0.0 {
+        return Err(Error::InvalidConfig("Replay speed must be 0 or more"));
+    }
+    let device = recording.device()?;
+    config.validate().map_err(Error::InvalidConfig)?;
+    let palm_state = palm::create_shared_state(&config);
+    let state: SharedState = Arc::new(RuntimeState::new(&config, device, palm_state.clone()));
+    signals::spawn_signal_handler(&state)?;
+
+    let clock = ReplayClock::new(speed, &state);
+    let config = Arc::new(config);
+    let mut handles = Vec::new();
+
+    for kind in [InputKind::Pen, InputKind::Touch] {
+        let run = match kind {
+            InputKind::Pen => config.run_pen(),
+            InputKind::Touch => config.run_touch(),
+        };
+        if !run || !recording.has(kind) {
+            continue;
+        }
+
+        let mut stream = ReplayStream::new(recording.events(kind), &clock);
+        let (config, state, palm) = (config.clone(), state.clone(), palm_state.clone());
+        handles.push((kind, thread::spawn(move || {
+            let grabbed = state.grab();
+            let result = match kind {
+                InputKind::Pen => VirtualDevice::pen(&config, device, &state).and_then(|mut sink| {
+                    input::forward_pen(&mut stream, &mut sink, &config, device, &state, palm, grabbed)
+                }),
+                InputKind::Touch => VirtualDevice::touch(&config, device, &state).and_then(|mut sink| {
+                    input::forward_touch(&mut stream, &mut sink, &config, device, &state, palm, grabbed)
+                }),
+            };
+            state.set_connected(kind, false);
+            match result {
+                Err(e) if !e.is_end_of_stream() => Err(e),
+                _ => {
+                    log::info!("[{}] Replay finished", kind);
+                    Ok(())
+                }
+            }
+        })));
+    }
+
+    // Wait for both inputs, then report the first failure
+    let results: Vec<_> = handles
+        .into_iter()
+        .map(|(kind, handle)| handle.join().unwrap_or_else(|_| Err(Error::ThreadPanicked(kind.to_string()))))
+        .collect();
+    results.into_iter().collect()
+}
+
+/// Record the raw pen and touch streams to `path` until interrupted.
+pub fn record(
+    config: &Config,
+    device: &DeviceProfile,
+    path: &Path,
+) -> Result<()> {
+    let file = File::create(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Cannot create {}: {}", path.display(), e)))?;
+    let mut writer = LineWriter::new(file);
+    let header = Header {
+        version: FORMAT_VERSION,
+        model: device.name.to_string(),
+        input_event_size: device.input_event_size,
+    };
+    serde_json::to_writer(&mut writer, &header)?;
+    writer.write_all(b"\n")?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register(signal, stop.clone())?;
+    }
+
+    let start = Instant::now();
+    let (sender, receiver) = mpsc::channel();
+    let inputs = [
+        (InputKind::Pen, config.run_pen(), &config.pen_device),
+        (InputKind::Touch, config.run_touch(), &config.touch_device),
+    ];
+    for (kind, _, device_path) in inputs.into_iter().filter(|(_, run, _)| *run) {
+        let (cleanup, mut channel) = ssh::open_input_stream(device_path, config, false)?;
+        let sender = sender.clone();
+        let size = device.input_event_size;
+
+        thread::spawn(move || {
+            let _cleanup = cleanup;
+            let mut buf = vec![0u8; size];
+            let result = loop {
+                if let Err(e) = channel.read_exact(&mut buf) {
+                    break Err(io::Error::new(e.kind(), format!("{} stream: {}", kind, e)));
+                }
+                let entry = Entry { t: start.elapsed().as_micros() as u64, input: kind, data: buf.clone() };
+                if sender.send(Ok(entry)).is_err() {
+                    return;
+                }
+            };
+            let _ = sender.send(result);
+        });
+    }
+    drop(sender);
+
+    eprintln!("Recording {} input to {} (Ctrl+C to stop)", device.name, path.display());
+
+    let mut count: u64 = 0;
+    while !stop.load(Ordering::Relaxed) {
+        match receiver.recv_timeout(REPLAY_POLL) {
+            Ok(Ok(entry)) => {
+                serde_json::to_writer(&mut writer, &entry)?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    eprintln!("Recorded {} events in {:.1}s", count, start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+/// Byte strings as lowercase hex.
+mod hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        // Slicing below splits at byte offsets
+        if !hex.is_ascii() {
+            return Err(serde::de::Error::custom("non-hex characters"));
+        }
+        if hex.len() % 2 != 0 {
+            return Err(serde::de::Error::custom("odd number of hex digits"));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recording_format() {
+        let entry = Entry { t: 1520, input: InputKind::Pen, data: vec![0x00, 0x8d, 0xff] };
+        let line = serde_json::to_string(&entry).unwrap();
+        assert_eq!(line, r#"{"t":1520,"input":"pen","data":"008dff"}"#);
+
+        let file = format!(
+            "{}\n{}\n{}\n",
+            r#"{"version":1,"model":"reMarkable 2","input_event_size":16}"#,
+            line,
+            r#"{"t":1600,"input":"touch","data":"01"}"#,
+        );
+        let recording = Recording::read(file.as_bytes()).unwrap();
+        assert_eq!(recording.header.model, "reMarkable 2");
+        assert_eq!(recording.entries[0], entry);
+        assert!(recording.has(InputKind::Touch));
+        assert!(Recording::read(file.replace("008dff", "0é0").as_bytes()).is_err());
+        assert_eq!(
+            recording.events(InputKind::Touch),
+            [(Duration::from_micros(1600), vec![0x01])]
+        );
+    }
+}
@@ -0,0 +1,38 @@
+//! Unix signal handling.
+//!
+//! - `SIGINT`/`SIGTERM`: shut down cleanly; a second one exits immediately
+//! - `SIGUSR1`: rotate the orientation a quarter turn clockwise
+//! - `SIGUSR2`: rotate the orientation a quarter turn counter-clockwise
+
+use std::thread;
+
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
+
+use crate::state::SharedState;
+
+/// Spawn a thread that handles signals for the lifetime of the process.
+pub fn spawn_signal_handler(state: &SharedState) -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGINT, SIGTERM, SIGUSR1, SIGUSR2])?;
+    let state = state.clone();
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let current = state.orientation();
+            match signal {
+                SIGINT | SIGTERM => {
+                    if !state.request_shutdown() {
+                        log::warn!("Exiting without cleanup");
+                        std::process::exit(1);
+                    }
+                    log::info!("Shutting down");
+                }
+                SIGUSR1 => state.set_orientation(current.rotated_clockwise()),
+                SIGUSR2 => state.set_orientation(current.rotated_counter_clockwise()),
+                _ => {}
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -1,5 +1,5 @@
-use std::io::Read;
-use std::net::{TcpStream, ToSocketAddrs};
+use std::io::{BufRead, BufReader, Read};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
@@ -8,7 +8,9 @@ use std::time::Duration;
 use ssh2::Session;
 
 use crate::config::{Auth, Config};
+use crate::error::{Error, Result};
 use crate::grab;
+use crate::state::SharedState;
 
 /// Watchdog file path on the tablet
 pub const WATCHDOG_FILE: &str = "/tmp/rm-pad-watchdog";
@@ -16,18 +18,36 @@ pub const WATCHDOG_FILE: &str = "/tmp/rm-pad-watchdog";
 /// How often to touch the watchdog file
 const WATCHDOG_INTERVAL: Duration = Duration::from_secs(2);
 
+/// Delay before following the tablet log again after the connection drops
+const LOG_TAIL_RETRY: Duration = Duration::from_secs(5);
+
+/// How often [`follow_command`] checks whether it should stop
+const FOLLOW_POLL: Duration = Duration::from_secs(1);
+
 /// Timeout for SSH operations
 const SSH_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Guard that holds the SSH session.
 pub struct GrabCleanup {
-    #[allow(dead_code)]
     session: Session,
+    stream: TcpStream,
 }
 
 impl GrabCleanup {
-    pub fn new(session: Session) -> Self {
-        Self { session }
+    pub fn new(session: Session, stream: TcpStream) -> Self {
+        Self { session, stream }
+    }
+
+    /// A handle to the underlying connection, e.g. to shut it down from
+    /// another thread.
+    pub fn stream(&self) -> std::io::Result<TcpStream> {
+        self.stream.try_clone()
+    }
+
+    /// Make blocking reads on the session's channels fail with `TimedOut`
+    /// after `timeout` instead of waiting forever.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.session.set_timeout(timeout.as_millis() as u32);
     }
 }
 
@@ -39,10 +59,10 @@ pub fn open_input_stream(
     device_path: &str,
     config: &Config,
     grab: bool,
-) -> Result<(GrabCleanup, ssh2::Channel), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(GrabCleanup, ssh2::Channel)> {
     log::info!("Connecting to {}", config.host);
 
-    let session = connect_and_authenticate(config)?;
+    let (session, stream) = connect_and_authenticate(config)?;
 
     if grab {
         prepare_grab(&session)?;
@@ -56,36 +76,35 @@ pub fn open_input_stream(
     channel.exec(&cmd)?;
 
     log::info!("Stream ready for {}", device_path);
-    Ok((GrabCleanup::new(session), channel))
+    Ok((GrabCleanup::new(session, stream), channel))
 }
 
 fn connect_and_authenticate(
     config: &Config,
-) -> Result<Session, Box<dyn std::error::Error + Send + Sync>> {
-    let addr = (config.host.as_str(), SSH_PORT)
-        .to_socket_addrs()?
-        .next()
-        .ok_or("Could not resolve host address")?;
+) -> Result<(Session, TcpStream)> {
+    let addr = resolve(&config.host)?;
     let tcp = TcpStream::connect_timeout(&addr, SSH_TIMEOUT)?;
 
+    let stream = tcp.try_clone()?;
+
     let mut session = Session::new()?;
     session.set_tcp_stream(tcp);
     session.handshake()?;
     authenticate(&mut session, &config.auth())?;
 
-    Ok(session)
+    Ok((session, stream))
 }
 
 /// Connect to the device via SSH for device detection purposes.
 /// Returns None if connection fails (e.g., device not available).
-pub fn connect_for_detection(config: &Config) -> Result<Session, Box<dyn std::error::Error + Send + Sync>> {
-    connect_and_authenticate(config)
+pub fn connect_for_detection(config: &Config) -> Result<Session> {
+    connect_and_authenticate(config).map(|(session, _)| session)
 }
 
 fn authenticate(
     session: &mut Session,
     auth: &Auth,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<()> {
     match auth {
         Auth::Key(path) => {
             session.userauth_pubkey_file(SSH_USER, None, path.as_ref(), None)?;
@@ -96,16 +115,23 @@ fn authenticate(
     }
 
     if !session.authenticated() {
-        return Err("SSH authentication failed".into());
+        return Err(Error::AuthenticationFailed);
     }
 
     Ok(())
 }
 
-fn prepare_grab(session: &Session) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+fn prepare_grab(session: &Session) -> Result<()> {
     let arch = grab::detect_arch(session)?;
     log::info!("Detected tablet architecture: {}", arch);
+    grab::tidy(session)?;
     grab::ensure_binary_valid(session, arch)?;
+
+    // The watchdog may have gone stale while the input was released
+    let mut channel = session.channel_session()?;
+    channel.exec(&format!("touch {}", WATCHDOG_FILE))?;
+    channel.read_to_string(&mut String::new())?;
+    channel.wait_close()?;
     Ok(())
 }
 
@@ -120,11 +146,8 @@ fn build_stream_command(device_path: &str, grab: bool) -> String {
 
 /// Touch the watchdog file once. Blocks until success or error.
 /// This MUST be called before starting grabbers.
-pub fn touch_watchdog_once(config: &Config) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let addr = (config.host.as_str(), SSH_PORT)
-        .to_socket_addrs()?
-        .next()
-        .ok_or("Could not resolve host address")?;
+pub fn touch_watchdog_once(config: &Config) -> Result<()> {
+    let addr = resolve(&config.host)?;
     let tcp = TcpStream::connect_timeout(&addr, SSH_TIMEOUT)?;
 
     let mut session = Session::new()?;
@@ -143,13 +166,14 @@ pub fn touch_watchdog_once(config: &Config) -> Result<(), Box<dyn std::error::Er
     Ok(())
 }
 
-/// Spawn a thread that periodically touches the watchdog file.
-/// Returns a stop flag.
-pub fn spawn_watchdog(config: &Config) -> Arc<AtomicBool> {
+/// Spawn a thread that periodically touches the watchdog file while the
+/// input is grabbed. Returns a stop flag.
+pub fn spawn_watchdog(config: &Config, state: &SharedState) -> Arc<AtomicBool> {
     let stop_flag = Arc::new(AtomicBool::new(false));
     let stop_flag_clone = stop_flag.clone();
     let host = config.host.clone();
     let auth = config.auth();
+    let state = state.clone();
 
     thread::spawn(move || {
         log::info!("Watchdog thread started");
@@ -160,6 +184,11 @@ pub fn spawn_watchdog(config: &Config) -> Arc<AtomicBool> {
                 break;
             }
 
+            if !state.grab() || state.shutting_down() {
+                thread::sleep(WATCHDOG_INTERVAL);
+                continue;
+            }
+
             if let Err(e) = touch_watchdog(&host, &auth) {
                 log::warn!("Watchdog touch failed: {}", e);
             }
@@ -171,11 +200,141 @@ pub fn spawn_watchdog(config: &Config) -> Arc<AtomicBool> {
     stop_flag
 }
 
-fn touch_watchdog(host: &str, auth: &Auth) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let addr = (host, SSH_PORT)
+fn touch_watchdog(host: &str, auth: &Auth) -> Result<()> {
+    run_command(host, auth, &format!("touch {}", WATCHDOG_FILE))?;
+    Ok(())
+}
+
+/// Remove the watchdog file so a grabber that is still running exits right away.
+pub fn remove_watchdog(config: &Config) -> Result<()> {
+    run_command(&config.host, &config.auth(), &format!("rm -f {}", WATCHDOG_FILE))?;
+    Ok(())
+}
+
+/// Remove rm-pad's files from the tablet, e.g. when exiting.
+pub fn clean_tablet(config: &Config) -> Result<()> {
+    run_command(&config.host, &config.auth(), &grab::clean_command())?;
+    Ok(())
+}
+
+/// Spawn a thread that follows the grab helper's log on the tablet and
+/// forwards new lines to the host log, tagged `[tablet]`, reconnecting as
+/// needed. Returns a stop flag.
+///
+/// The log's size is read before returning, so lines the helper writes
+/// while the tail connects, such as the first grab failure, are shown too.
+pub fn spawn_log_tail(config: &Config) -> Arc<AtomicBool> {
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let stop_flag_clone = stop_flag.clone();
+    let host = config.host.clone();
+    let auth = config.auth();
+    let mut offset = run_command(&host, &auth, &grab::log_size_command())
+        .ok()
+        .and_then(|size| size.trim().parse::<u64>().ok());
+
+    thread::spawn(move || {
+        while !stop_flag_clone.load(Ordering::Relaxed) {
+            // Pick up where the last connection left off
+            let command = match offset {
+                Some(offset) => grab::follow_log_command(offset),
+                None => grab::log_command(true, 0),
+            };
+            let result = follow_command(&host, &auth, &command, &stop_flag_clone, |line| {
+                log::log!(grab::log_level(line), "[tablet] {}", line);
+                offset = offset.map(|offset| offset + line.len() as u64 + 1);
+            });
+            if let Err(e) = result {
+                log::debug!("Tablet log tail ended: {}", e);
+            }
+            thread::sleep(LOG_TAIL_RETRY);
+        }
+    });
+
+    stop_flag
+}
+
+/// Run a command on the tablet, calling `on_line` for each line of stdout
+/// as it arrives.
+pub fn stream_command(
+    host: &str,
+    auth: &Auth,
+    command: &str,
+    mut on_line: impl FnMut(&str),
+) -> Result<()> {
+    let session = open_session(host, auth)?;
+    let mut channel = session.channel_session()?;
+    channel.exec(command)?;
+
+    for line in BufReader::new(&mut channel).lines() {
+        on_line(line?.trim_end());
+    }
+    channel.wait_close()?;
+
+    Ok(())
+}
+
+/// Like [`stream_command`], but for commands that run until stopped: reads
+/// time out every [`FOLLOW_POLL`] to check `stop`, so the connection is
+/// closed soon after it is set.
+pub fn follow_command(
+    host: &str,
+    auth: &Auth,
+    command: &str,
+    stop: &AtomicBool,
+    mut on_line: impl FnMut(&str),
+) -> Result<()> {
+    let session = open_session(host, auth)?;
+    let mut channel = session.channel_session()?;
+    channel.exec(command)?;
+    session.set_timeout(FOLLOW_POLL.as_millis() as u32);
+
+    let mut reader = BufReader::new(&mut channel);
+    let mut line = Vec::new();
+    while !stop.load(Ordering::Relaxed) {
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                on_line(String::from_utf8_lossy(&line).trim_end());
+                line.clear();
+            }
+            // Partial lines stay in `line` until the rest arrives
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a command in an existing session, returning its stdout and exit status.
+pub fn exec(session: &Session, command: &str) -> Result<(String, i32)> {
+    let mut channel = session.channel_session()?;
+    channel.exec(command)?;
+
+    let mut output = String::new();
+    channel.read_to_string(&mut output)?;
+    channel.close()?;
+    channel.wait_close()?;
+
+    Ok((output, channel.exit_status()?))
+}
+
+/// Check that the tablet accepts TCP connections on the SSH port.
+pub fn check_reachable(host: &str) -> Result<()> {
+    let addr = resolve(host)?;
+    TcpStream::connect_timeout(&addr, SSH_TIMEOUT)?;
+    Ok(())
+}
+
+fn resolve(host: &str) -> Result<SocketAddr> {
+    (host, SSH_PORT)
         .to_socket_addrs()?
         .next()
-        .ok_or("Could not resolve host address")?;
+        .ok_or_else(|| Error::HostNotFound(host.to_string()))
+}
+
+fn open_session(host: &str, auth: &Auth) -> Result<Session> {
+    let addr = resolve(host)?;
     let tcp = TcpStream::connect_timeout(&addr, SSH_TIMEOUT)?;
 
     let mut session = Session::new()?;
@@ -183,12 +342,22 @@ fn touch_watchdog(host: &str, auth: &Auth) -> Result<(), Box<dyn std::error::Err
     session.handshake()?;
     authenticate(&mut session, auth)?;
 
+    Ok(session)
+}
+
+/// Run a command on the tablet over a fresh connection and return its stdout.
+pub fn run_command(
+    host: &str,
+    auth: &Auth,
+    command: &str,
+) -> Result<String> {
+    let session = open_session(host, auth)?;
     let mut channel = session.channel_session()?;
-    channel.exec(&format!("touch {}", WATCHDOG_FILE))?;
+    channel.exec(command)?;
 
     let mut output = String::new();
     channel.read_to_string(&mut output)?;
     channel.wait_close()?;
 
-    Ok(())
+    Ok(output)
 }
@@ -0,0 +1,400 @@
+//! Runtime state shared between the forwarding threads and whatever
+//! controls them while rm-pad is running.
+
+use std::fmt;
+use std::net::{Shutdown, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use evdevil::event::{EventType, InputEvent, Syn};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::device::DeviceProfile;
+use crate::orientation::Orientation;
+use crate::palm::SharedPalmState;
+
+/// One of the two forwarded inputs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputKind {
+    Pen,
+    Touch,
+}
+
+impl fmt::Display for InputKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputKind::Pen => write!(f, "pen"),
+            InputKind::Touch => write!(f, "touch"),
+        }
+    }
+}
+
+impl FromStr for InputKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pen" => Ok(InputKind::Pen),
+            "touch" => Ok(InputKind::Touch),
+            _ => Err(format!("Invalid input '{}'. Valid values: pen, touch", s)),
+        }
+    }
+}
+
+struct InputState {
+    connected: AtomicBool,
+    enabled: AtomicBool,
+    frames: AtomicU64,
+    connections: AtomicU64,
+    bytes: AtomicU64,
+    parse_failures: AtomicU64,
+    palm_suppressed: AtomicU64,
+    contacts: AtomicU64,
+    /// Microseconds from `RuntimeState::started` to the last event, 0 if none.
+    last_event_us: AtomicU64,
+    /// Microseconds from `RuntimeState::started` to the last sign of life
+    /// from the forwarding thread, 0 if none.
+    progress_us: AtomicU64,
+    /// Connection of the current stream, shut down to unblock its reader.
+    stream: Mutex<Option<TcpStream>>,
+}
+
+impl InputState {
+    fn new() -> Self {
+        Self {
+            connected: AtomicBool::new(false),
+            enabled: AtomicBool::new(true),
+            frames: AtomicU64::new(0),
+            connections: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            parse_failures: AtomicU64::new(0),
+            palm_suppressed: AtomicU64::new(0),
+            contacts: AtomicU64::new(0),
+            last_event_us: AtomicU64::new(0),
+            progress_us: AtomicU64::new(0),
+            stream: Mutex::new(None),
+        }
+    }
+
+    fn status(&self, started: Instant) -> InputStatus {
+        let last_event_us = self.last_event_us.load(Ordering::Relaxed);
+        InputStatus {
+            connected: self.connected.load(Ordering::Relaxed),
+            enabled: self.enabled.load(Ordering::Relaxed),
+            frames: self.frames.load(Ordering::Relaxed),
+            reconnects: self.connections.load(Ordering::Relaxed).saturating_sub(1),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            parse_failures: self.parse_failures.load(Ordering::Relaxed),
+            palm_suppressed: self.palm_suppressed.load(Ordering::Relaxed),
+            contacts: self.contacts.load(Ordering::Relaxed),
+            last_event_age_ms: (last_event_us > 0).then(|| {
+                (started.elapsed().as_micros() as u64).saturating_sub(last_event_us) / 1000
+            }),
+        }
+    }
+}
+
+/// Frames an output viewer may fall behind before it is dropped, a couple
+/// of seconds of pen input.
+const OUTPUT_TAP_FRAMES: usize = 512;
+
+pub struct RuntimeState {
+    model: &'static str,
+    orientation: Mutex<Orientation>,
+    paused: AtomicBool,
+    grab: AtomicBool,
+    pen: InputState,
+    touch: InputState,
+    palm: Option<SharedPalmState>,
+    shutdown: AtomicBool,
+    started: Instant,
+    /// Viewers of the frames written to the virtual devices.
+    output_taps: Mutex<Vec<(Vec<InputKind>, mpsc::SyncSender<OutputFrame>)>>,
+    output_tap_count: AtomicUsize,
+}
+
+pub type SharedState = Arc<RuntimeState>;
+
+/// Snapshot of the runtime state, as reported by the `status` control command.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub model: String,
+    pub orientation: Orientation,
+    pub paused: bool,
+    pub grab: bool,
+    pub pen: InputStatus,
+    pub touch: InputStatus,
+    /// `None` when palm rejection is off.
+    pub palm: Option<PalmStatus>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct InputStatus {
+    pub connected: bool,
+    pub enabled: bool,
+    /// Frames written to the virtual device.
+    pub frames: u64,
+    pub reconnects: u64,
+    /// Bytes received from the tablet.
+    pub bytes: u64,
+    /// Raw events that couldn't be parsed.
+    pub parse_failures: u64,
+    /// Frames dropped by palm rejection.
+    pub palm_suppressed: u64,
+    /// Contacts currently forwarded (the pen counts while touching).
+    pub contacts: u64,
+    /// Time since the last event from the tablet, `None` before the first.
+    pub last_event_age_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PalmStatus {
+    pub pen_down: bool,
+    pub pen_in_range: bool,
+}
+
+/// One frame written to a virtual device, as streamed to `rm-pad dump --output`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputFrame {
+    pub input: InputKind,
+    /// Time of the write, in microseconds since the Unix epoch.
+    pub time_us: u128,
+    /// `[type, code, value]` of each event, without the closing `SYN_REPORT`.
+    pub events: Vec<(u16, u16, i32)>,
+}
+
+impl OutputFrame {
+    /// `events` of the virtual `input` device, written now.
+    pub fn new(input: InputKind, events: &[InputEvent]) -> Self {
+        let events = match events.split_last() {
+            Some((last, rest)) if last.event_type() == EventType::SYN && last.raw_code() == Syn::REPORT.raw() => rest,
+            _ => events,
+        };
+        Self {
+            input,
+            time_us: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros(),
+            events: events.iter().map(|e| (e.event_type().raw(), e.raw_code(), e.raw_value())).collect(),
+        }
+    }
+}
+
+impl RuntimeState {
+    pub fn new(
+        config: &Config,
+        device: &'static DeviceProfile,
+        palm: Option<SharedPalmState>,
+    ) -> Self {
+        Self {
+            model: device.name,
+            orientation: Mutex::new(config.orientation),
+            paused: AtomicBool::new(false),
+            grab: AtomicBool::new(config.grab_input),
+            pen: InputState::new(),
+            touch: InputState::new(),
+            palm,
+            shutdown: AtomicBool::new(false),
+            started: Instant::now(),
+            output_taps: Mutex::new(Vec::new()),
+            output_tap_count: AtomicUsize::new(0),
+        }
+    }
+
+    fn input(&self, kind: InputKind) -> &InputState {
+        match kind {
+            InputKind::Pen => &self.pen,
+            InputKind::Touch => &self.touch,
+        }
+    }
+
+    /// Current orientation. Forwarding threads check this every frame and
+    /// rebuild their virtual devices when it changes.
+    pub fn orientation(&self) -> Orientation {
+        *self.orientation.lock().unwrap()
+    }
+
+    pub fn set_orientation(&self, orientation: Orientation) {
+        let mut current = self.orientation.lock().unwrap();
+        if *current != orientation {
+            log::info!("Orientation changed: {} -> {}", *current, orientation);
+            *current = orientation;
+        }
+    }
+
+    /// Whether the tablet input should be grabbed. Forwarding threads
+    /// reconnect when this no longer matches their stream.
+    pub fn grab(&self) -> bool {
+        self.grab.load(Ordering::Relaxed)
+    }
+
+    /// Change the grab setting. The current connections are shut down so
+    /// the forwarding threads reopen their streams right away, even while
+    /// the tablet is idle.
+    pub fn set_grab(&self, grab: bool) {
+        if self.grab.swap(grab, Ordering::Relaxed) != grab {
+            log::info!("{} tablet input", if grab { "Grabbing" } else { "Releasing" });
+            self.close_streams();
+        }
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        if self.paused.swap(paused, Ordering::Relaxed) != paused {
+            log::info!("Forwarding {}", if paused { "paused" } else { "resumed" });
+        }
+    }
+
+    pub fn set_enabled(&self, kind: InputKind, enabled: bool) {
+        if self.input(kind).enabled.swap(enabled, Ordering::Relaxed) != enabled {
+            log::info!("{} input {}", kind, if enabled { "enabled" } else { "disabled" });
+        }
+    }
+
+    /// Whether frames from `kind` should currently reach the virtual devices.
+    pub fn forwarding(&self, kind: InputKind) -> bool {
+        !self.paused.load(Ordering::Relaxed) && self.input(kind).enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_connected(&self, kind: InputKind, connected: bool) {
+        let input = self.input(kind);
+        if !input.connected.swap(connected, Ordering::Relaxed) && connected {
+            input.connections.fetch_add(1, Ordering::Relaxed);
+        }
+        if !connected {
+            input.contacts.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Remember the connection `kind` is streaming over so a shutdown can
+    /// interrupt it.
+    pub fn set_stream(&self, kind: InputKind, stream: TcpStream) {
+        let mut current = self.input(kind).stream.lock().unwrap();
+        if self.shutting_down() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        *current = Some(stream);
+    }
+
+    /// Ask the forwarding threads to stop: their connections are shut down,
+    /// so blocked reads fail and the threads release their devices and exit.
+    /// Returns false if a shutdown was already requested.
+    pub fn request_shutdown(&self) -> bool {
+        if self.shutdown.swap(true, Ordering::Relaxed) {
+            return false;
+        }
+        self.close_streams();
+        true
+    }
+
+    fn close_streams(&self) {
+        for input in [&self.pen, &self.touch] {
+            if let Some(stream) = input.stream.lock().unwrap().take() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+
+    pub fn shutting_down(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+
+    pub fn count_frame(&self, kind: InputKind) {
+        self.input(kind).frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Note a raw event of `bytes` bytes received from the tablet.
+    pub fn count_event(&self, kind: InputKind, bytes: usize) {
+        let input = self.input(kind);
+        input.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        let since_start = (self.started.elapsed().as_micros() as u64).max(1);
+        input.last_event_us.store(since_start, Ordering::Relaxed);
+    }
+
+    /// Note that the forwarding thread for `kind` is alive: it read from
+    /// its stream, even if that timed out, or is (re)connecting.
+    pub fn report_progress(&self, kind: InputKind) {
+        let since_start = (self.started.elapsed().as_micros() as u64).max(1);
+        self.input(kind).progress_us.store(since_start, Ordering::Relaxed);
+    }
+
+    /// Whether the forwarding thread for `kind` reported progress within
+    /// `window`.
+    pub fn made_progress(&self, kind: InputKind, window: Duration) -> bool {
+        let progress_us = self.input(kind).progress_us.load(Ordering::Relaxed);
+        progress_us > 0
+            && (self.started.elapsed().as_micros() as u64).saturating_sub(progress_us)
+                <= window.as_micros() as u64
+    }
+
+    pub fn count_parse_failure(&self, kind: InputKind) {
+        self.input(kind).parse_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_palm_suppressed(&self, kind: InputKind) {
+        self.input(kind).palm_suppressed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_contacts(&self, kind: InputKind, contacts: usize) {
+        self.input(kind).contacts.store(contacts as u64, Ordering::Relaxed);
+    }
+
+    /// Receive a copy of every frame written to the virtual devices of
+    /// `inputs`. The receiver is closed if it falls `OUTPUT_TAP_FRAMES`
+    /// frames behind, rather than holding up forwarding.
+    pub fn tap_output(&self, inputs: &[InputKind]) -> mpsc::Receiver<OutputFrame> {
+        let (sender, receiver) = mpsc::sync_channel(OUTPUT_TAP_FRAMES);
+        let mut taps = self.output_taps.lock().unwrap();
+        taps.push((inputs.to_vec(), sender));
+        self.output_tap_count.store(taps.len(), Ordering::Relaxed);
+        receiver
+    }
+
+    /// Pass a frame written to the virtual `kind` device on to its viewers.
+    pub fn publish_output(&self, kind: InputKind, events: &[InputEvent]) {
+        if self.output_tap_count.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let mut taps = self.output_taps.lock().unwrap();
+        let frame = OutputFrame::new(kind, events);
+
+        // Viewers that went away or fell behind are dropped on their next frame
+        taps.retain(|(inputs, sender)| {
+            if !inputs.contains(&kind) {
+                return true;
+            }
+            match sender.try_send(frame.clone()) {
+                Ok(()) => true,
+                Err(mpsc::TrySendError::Full(_)) => {
+                    log::warn!("Dropping an output viewer that fell behind");
+                    false
+                }
+                Err(mpsc::TrySendError::Disconnected(_)) => false,
+            }
+        });
+        self.output_tap_count.store(taps.len(), Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> Status {
+        let palm = self.palm.as_ref().and_then(|palm| {
+            let palm = palm.lock().ok()?;
+            Some(PalmStatus {
+                pen_down: palm.pen_down,
+                pen_in_range: palm.pen_in_range,
+            })
+        });
+
+        Status {
+            model: self.model.to_string(),
+            orientation: self.orientation(),
+            paused: self.paused.load(Ordering::Relaxed),
+            grab: self.grab(),
+            pen: self.pen.status(self.started),
+            touch: self.touch.status(self.started),
+            palm,
+        }
+    }
+}
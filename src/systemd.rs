@@ -0,0 +1,111 @@
+//! systemd service notifications (`sd_notify`) for `Type=notify` units.
+//!
+//! Messages go to the datagram socket in `$NOTIFY_SOCKET`; outside systemd
+//! the variable is unset and nothing is sent.
+
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use crate::state::{InputKind, InputStatus, Status};
+
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Connect to the socket systemd passed in the environment, if any.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("NOTIFY_SOCKET").ok()?;
+
+        // A leading '@' names a socket in the abstract namespace
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+            None => SocketAddr::from_pathname(&path),
+        };
+        let addr = match addr {
+            Ok(addr) => addr,
+            Err(e) => {
+                log::warn!("Ignoring NOTIFY_SOCKET {}: {}", path, e);
+                return None;
+            }
+        };
+
+        let socket = UnixDatagram::unbound().ok()?;
+        Some(Self { socket, addr, watchdog: watchdog_interval() })
+    }
+
+    /// Send one or more newline-separated `KEY=value` assignments.
+    pub fn notify(&self, message: &str) -> io::Result<()> {
+        self.socket.send_to_addr(message.as_bytes(), &self.addr)?;
+        Ok(())
+    }
+
+    /// Timeout after which systemd considers the service hung unless it
+    /// receives `WATCHDOG=1`.
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.watchdog
+    }
+}
+
+/// Watchdog timeout from `WATCHDOG_USEC`, if it is meant for this process.
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec))
+}
+
+/// One-line summary of the forwarding state for `STATUS=`.
+pub fn status_line(status: &Status, run_pen: bool, run_touch: bool) -> String {
+    if status.paused {
+        return "Paused".into();
+    }
+
+    let inputs = [(InputKind::Pen, run_pen, &status.pen), (InputKind::Touch, run_touch, &status.touch)];
+    let (connected, waiting): (Vec<_>, Vec<_>) = inputs
+        .iter()
+        .filter(|(_, run, _)| *run)
+        .partition(|(_, _, input)| input.connected);
+    let names = |inputs: &[&(InputKind, bool, &InputStatus)]| {
+        inputs.iter().map(|(kind, _, _)| kind.to_string()).collect::<Vec<_>>().join(" and ")
+    };
+
+    match (connected.is_empty(), waiting.is_empty()) {
+        (true, _) => format!("Waiting for {}", status.model),
+        (false, true) => format!("Forwarding {}", names(&connected)),
+        (false, false) => format!("Forwarding {}, reconnecting {}", names(&connected), names(&waiting)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orientation::Orientation;
+
+    #[test]
+    fn test_status_line() {
+        let input = |connected| InputStatus { connected, enabled: true, ..Default::default() };
+        let mut status = Status {
+            model: "reMarkable 2".into(),
+            orientation: Orientation::default(),
+            paused: false,
+            grab: true,
+            pen: input(true),
+            touch: input(false),
+            palm: None,
+        };
+
+        assert_eq!(status_line(&status, true, true), "Forwarding pen, reconnecting touch");
+        assert_eq!(status_line(&status, true, false), "Forwarding pen");
+        assert_eq!(status_line(&status, false, true), "Waiting for reMarkable 2");
+        status.paused = true;
+        assert_eq!(status_line(&status, true, true), "Paused");
+    }
+}
@@ -0,0 +1,289 @@
+//! Coordinate transforms from device space to output space.
+//!
+//! By default input follows one of the four `Orientation`s. An affine
+//! `pen_transform`/`touch_transform` matrix in the config replaces the
+//! orientation for that device, allowing mirroring, small rotations and
+//! scaling, optionally limited to a crop of the device area.
+
+use std::f64::consts::FRAC_PI_2;
+
+use serde::Deserialize;
+
+use crate::orientation::Orientation;
+
+/// A rectangle `[x_min, y_min, x_max, y_max]` in raw device coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(from = "[i32; 4]")]
+pub struct Crop {
+    min: (i32, i32),
+    max: (i32, i32),
+}
+
+impl From<[i32; 4]> for Crop {
+    fn from([x_min, y_min, x_max, y_max]: [i32; 4]) -> Self {
+        Self { min: (x_min, y_min), max: (x_max, y_max) }
+    }
+}
+
+impl Crop {
+    /// Whether the rectangle has an area, i.e. its minimum lies below its maximum.
+    pub fn is_valid(&self) -> bool {
+        self.min.0 < self.max.0 && self.min.1 < self.max.1
+    }
+}
+
+/// A 2×3 affine matrix `[[a, b, c], [d, e, f]]` mapping raw device coordinates
+/// to output coordinates: `x' = a·x + b·y + c`, `y' = d·x + e·y + f`.
+///
+/// The output area is the bounding box of the transformed device area, or
+/// of the crop if one is set, moved to start at zero, so the offset `c`/`f`
+/// doesn't matter.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(from = "[[f64; 3]; 2]")]
+pub struct AffineTransform {
+    matrix: [[f64; 3]; 2],
+    crop: Option<Crop>,
+}
+
+impl From<[[f64; 3]; 2]> for AffineTransform {
+    fn from(matrix: [[f64; 3]; 2]) -> Self {
+        Self { matrix, crop: None }
+    }
+}
+
+impl AffineTransform {
+    /// Only map `crop` of the device area; input outside it sticks to its
+    /// edges.
+    pub fn with_crop(self, crop: Option<Crop>) -> Self {
+        Self { crop, ..self }
+    }
+
+    fn determinant(&self) -> f64 {
+        let [[a, b, _], [d, e, _]] = self.matrix;
+        a * e - b * d
+    }
+
+    /// Whether the matrix can be inverted, i.e. doesn't collapse the area to a line.
+    pub fn is_invertible(&self) -> bool {
+        self.determinant().abs() > f64::EPSILON
+    }
+
+    fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let [[a, b, c], [d, e, f]] = self.matrix;
+        (a * x + b * y + c, d * x + e * y + f)
+    }
+
+    /// Fit the matrix to a device area of `0..=x_max`×`0..=y_max`, or the
+    /// part of it inside the crop.
+    pub fn bounded(self, x_max: i32, y_max: i32) -> BoundedTransform {
+        let (input_min, input_max) = match self.crop {
+            Some(Crop { min, max }) => (
+                (min.0.clamp(0, x_max), min.1.clamp(0, y_max)),
+                (max.0.clamp(0, x_max), max.1.clamp(0, y_max)),
+            ),
+            None => ((0, 0), (x_max, y_max)),
+        };
+        let corners = [
+            input_min,
+            (input_max.0, input_min.1),
+            (input_min.0, input_max.1),
+            input_max,
+        ]
+        .map(|(x, y)| self.apply(x as f64, y as f64));
+        let min_x = corners.iter().map(|c| c.0).fold(f64::INFINITY, f64::min);
+        let min_y = corners.iter().map(|c| c.1).fold(f64::INFINITY, f64::min);
+        let max_x = corners.iter().map(|c| c.0).fold(f64::NEG_INFINITY, f64::max);
+        let max_y = corners.iter().map(|c| c.1).fold(f64::NEG_INFINITY, f64::max);
+        BoundedTransform {
+            affine: self,
+            input_min,
+            input_max,
+            min: (min_x, min_y),
+            output_max: ((max_x - min_x).round() as i32, (max_y - min_y).round() as i32),
+        }
+    }
+
+    /// Rotate (and mirror) a tilt vector with the linear part of the matrix,
+    /// keeping its length so scaling doesn't exaggerate the tilt.
+    pub fn transform_tilt(&self, tilt_x: i32, tilt_y: i32, range: i32) -> (i32, i32) {
+        let [[a, b, _], [d, e, _]] = self.matrix;
+        let scale = self.determinant().abs().sqrt();
+        let (tx, ty) = (tilt_x as f64, tilt_y as f64);
+        let out_x = (a * tx + b * ty) / scale;
+        let out_y = (d * tx + e * ty) / scale;
+        (
+            (out_x.round() as i32).clamp(-range, range),
+            (out_y.round() as i32).clamp(-range, range),
+        )
+    }
+
+    /// Turn a contact's ellipse angle (a signed quarter turn in
+    /// `-angle_max..=angle_max`) by the matrix's rotation, mirroring it first
+    /// if the matrix flips the area.
+    pub fn transform_angle(&self, angle: i32, angle_max: i32) -> i32 {
+        let [[a, _, _], [d, _, _]] = self.matrix;
+        let mirrored = if self.determinant() < 0.0 { -angle } else { angle };
+        let rotation = d.atan2(a) / FRAC_PI_2 * angle_max as f64;
+        let period = 2 * angle_max;
+        let turned = mirrored + rotation.round() as i32;
+        (turned + angle_max).rem_euclid(period) - angle_max
+    }
+}
+
+/// An [`AffineTransform`] fitted to a device area: the bounding box of the
+/// transformed area is moved to start at zero and becomes the output area.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundedTransform {
+    affine: AffineTransform,
+    /// Device area that is mapped, the crop clamped to the device.
+    input_min: (i32, i32),
+    input_max: (i32, i32),
+    /// Bounding box minimum, subtracted from every transformed point.
+    min: (f64, f64),
+    output_max: (i32, i32),
+}
+
+impl BoundedTransform {
+    /// Maximum output coordinates.
+    pub fn output_dimensions(&self) -> (i32, i32) {
+        self.output_max
+    }
+
+    /// Transform a point, clamped to the mapped device area and the output area.
+    pub fn transform_point(&self, x: i32, y: i32) -> (i32, i32) {
+        let x = x.clamp(self.input_min.0, self.input_max.0);
+        let y = y.clamp(self.input_min.1, self.input_max.1);
+        let (tx, ty) = self.affine.apply(x as f64, y as f64);
+        (
+            ((tx - self.min.0).round() as i32).clamp(0, self.output_max.0),
+            ((ty - self.min.1).round() as i32).clamp(0, self.output_max.1),
+        )
+    }
+}
+
+/// How a device's coordinates are mapped to the virtual device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoordinateTransform {
+    Orientation(Orientation),
+    Affine(BoundedTransform),
+}
+
+impl CoordinateTransform {
+    /// The configured matrix, fitted to a device area of
+    /// `0..=x_max`×`0..=y_max`, if any, otherwise the current orientation.
+    pub fn new(affine: Option<AffineTransform>, orientation: Orientation, x_max: i32, y_max: i32) -> Self {
+        match affine {
+            Some(affine) => CoordinateTransform::Affine(affine.bounded(x_max, y_max)),
+            None => CoordinateTransform::Orientation(orientation),
+        }
+    }
+
+    /// The same transform after the orientation changed. A configured
+    /// matrix doesn't follow the orientation.
+    pub fn with_orientation(self, orientation: Orientation) -> Self {
+        match self {
+            CoordinateTransform::Orientation(_) => CoordinateTransform::Orientation(orientation),
+            affine => affine,
+        }
+    }
+
+    pub fn transform_touch(&self, x: i32, y: i32, x_max: i32, y_max: i32) -> (i32, i32) {
+        match self {
+            CoordinateTransform::Orientation(o) => o.transform_touch(x, y, x_max, y_max),
+            CoordinateTransform::Affine(t) => t.transform_point(x, y),
+        }
+    }
+
+    pub fn transform_pen(&self, x: i32, y: i32, x_max: i32, y_max: i32) -> (i32, i32) {
+        match self {
+            CoordinateTransform::Orientation(o) => o.transform_pen(x, y, x_max, y_max),
+            CoordinateTransform::Affine(t) => t.transform_point(x, y),
+        }
+    }
+
+    pub fn transform_tilt(&self, tilt_x: i32, tilt_y: i32, range: i32) -> (i32, i32) {
+        match self {
+            CoordinateTransform::Orientation(o) => o.transform_tilt(tilt_x, tilt_y),
+            CoordinateTransform::Affine(t) => t.affine.transform_tilt(tilt_x, tilt_y, range),
+        }
+    }
+
+    pub fn transform_touch_shape(
+        &self,
+        major: Option<i32>,
+        minor: Option<i32>,
+        angle: Option<i32>,
+        angle_max: i32,
+    ) -> (Option<i32>, Option<i32>, Option<i32>) {
+        match self {
+            CoordinateTransform::Orientation(o) => {
+                o.transform_touch_shape(major, minor, angle, angle_max)
+            }
+            CoordinateTransform::Affine(t) => {
+                (major, minor, angle.map(|a| t.affine.transform_angle(a, angle_max)))
+            }
+        }
+    }
+
+    pub fn touch_output_dimensions(&self, x_max: i32, y_max: i32) -> (i32, i32) {
+        match self {
+            CoordinateTransform::Orientation(o) => o.touch_output_dimensions(x_max, y_max),
+            CoordinateTransform::Affine(t) => t.output_dimensions(),
+        }
+    }
+
+    pub fn pen_output_dimensions(&self, x_max: i32, y_max: i32) -> (i32, i32) {
+        match self {
+            CoordinateTransform::Orientation(o) => o.pen_output_dimensions(x_max, y_max),
+            CoordinateTransform::Affine(t) => t.output_dimensions(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror() {
+        let t = AffineTransform::from([[-1.0, 0.0, 100.0], [0.0, 1.0, 0.0]]);
+        let bounded = t.bounded(100, 50);
+        assert_eq!(bounded.output_dimensions(), (100, 50));
+        assert_eq!(bounded.transform_point(10, 20), (90, 20));
+        assert_eq!(t.transform_tilt(5, 7, 60), (-5, 7));
+        assert_eq!(t.transform_angle(40, 127), -40);
+    }
+
+    #[test]
+    fn test_bounding_box_ignores_offset() {
+        // Mirrored without an offset, the area lies at negative X
+        let t = AffineTransform::from([[-1.0, 0.0, 0.0], [0.0, 2.0, -50.0]]).bounded(100, 50);
+        assert_eq!(t.output_dimensions(), (100, 100));
+        assert_eq!(t.transform_point(0, 0), (100, 0));
+        assert_eq!(t.transform_point(75, 40), (25, 80));
+        assert_eq!(t.transform_point(100, 50), (0, 100));
+    }
+
+    #[test]
+    fn test_crop() {
+        // The right half, mirrored, clamped to the device area
+        let crop = Crop::from([50, 0, 150, 50]);
+        let t = AffineTransform::from([[-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+            .with_crop(Some(crop))
+            .bounded(100, 50);
+        assert_eq!(t.output_dimensions(), (50, 50));
+        assert_eq!(t.transform_point(50, 10), (50, 10));
+        assert_eq!(t.transform_point(90, 10), (10, 10));
+        // Input outside the crop sticks to its edge
+        assert_eq!(t.transform_point(20, 10), (50, 10));
+    }
+
+    #[test]
+    fn test_quarter_turn_matches_orientation() {
+        // Swap X and Y, like LandscapeRight for touch
+        let t = AffineTransform::from([[0.0, 1.0, 0.0], [1.0, 0.0, 0.0]]).bounded(100, 200);
+        let o = Orientation::LandscapeRight;
+        assert_eq!(t.output_dimensions(), o.touch_output_dimensions(100, 200));
+        assert_eq!(t.transform_point(50, 100), o.transform_touch(50, 100, 100, 200));
+    }
+}
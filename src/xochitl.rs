@@ -0,0 +1,114 @@
+//! Following the orientation chosen in the tablet UI (xochitl).
+//!
+//! xochitl keeps its settings in an INI file on the tablet. When
+//! `follow_tablet_orientation` is set, a loop on the tablet prints the
+//! setting every few seconds over one SSH connection, and the matching
+//! `Orientation` is applied whenever it changes. Orientations set in
+//! between, e.g. through the control socket, stay until the tablet UI
+//! rotates again.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::orientation::Orientation;
+use crate::ssh;
+use crate::state::SharedState;
+
+/// xochitl settings file on the tablet.
+const XOCHITL_CONF: &str = "/home/root/.config/remarkable/xochitl.conf";
+
+/// Setting holding the UI orientation.
+const ORIENTATION_KEY: &str = "Orientation";
+
+/// How often to re-read the settings file, and to reconnect after the
+/// connection drops.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Extract the orientation from the contents of xochitl.conf.
+///
+/// Accepts xochitl's `portrait`/`landscape`, rotations in degrees and
+/// rm-pad's own orientation names.
+pub fn parse_orientation(conf: &str) -> Option<Orientation> {
+    let value = conf.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(ORIENTATION_KEY)
+            .then(|| value.trim().to_lowercase())
+    })?;
+
+    match value.as_str() {
+        "landscape" | "90" => Some(Orientation::LandscapeRight),
+        "0" => Some(Orientation::Portrait),
+        "180" => Some(Orientation::Inverted),
+        "270" => Some(Orientation::LandscapeLeft),
+        other => other.parse().ok(),
+    }
+}
+
+/// Spawn a thread that keeps `state`'s orientation in sync with the tablet UI.
+/// Returns a stop flag.
+pub fn spawn_orientation_watcher(config: &Config, state: &SharedState) -> Arc<AtomicBool> {
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let stop_flag_clone = stop_flag.clone();
+    let host = config.host.clone();
+    let auth = config.auth();
+    let state = state.clone();
+    // One line per poll; an empty one when the setting is missing
+    let command = format!(
+        "while :; do grep -i '^[[:space:]]*{}[[:space:]]*=' {} || echo; sleep {}; done",
+        ORIENTATION_KEY,
+        XOCHITL_CONF,
+        POLL_INTERVAL.as_secs()
+    );
+
+    thread::spawn(move || {
+        log::info!("Following tablet orientation from {}", XOCHITL_CONF);
+        let mut last = None;
+        let mut warned = false;
+
+        while !stop_flag_clone.load(Ordering::Relaxed) {
+            let result = ssh::follow_command(&host, &auth, &command, &stop_flag_clone, |line| {
+                match parse_orientation(line) {
+                    Some(orientation) => {
+                        // Only follow changes, so orientations set in the
+                        // meantime aren't reverted on every poll
+                        if last != Some(orientation) {
+                            state.set_orientation(orientation);
+                            last = Some(orientation);
+                        }
+                        warned = false;
+                    }
+                    None if !warned => {
+                        log::warn!("No {} setting found in {}", ORIENTATION_KEY, XOCHITL_CONF);
+                        warned = true;
+                    }
+                    None => {}
+                }
+            });
+            if let Err(e) = result {
+                log::debug!("Failed to read {}: {}", XOCHITL_CONF, e);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+
+    stop_flag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_orientation() {
+        let conf = "[General]\nOrientation=landscape\nWifiEnabled=true\n";
+        assert_eq!(parse_orientation(conf), Some(Orientation::LandscapeRight));
+        assert_eq!(parse_orientation("orientation = portrait"), Some(Orientation::Portrait));
+        assert_eq!(parse_orientation("Orientation=270"), Some(Orientation::LandscapeLeft));
+        assert_eq!(parse_orientation("[General]\nWifiEnabled=true"), None);
+    }
+}
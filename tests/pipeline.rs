@@ -0,0 +1,352 @@
+//! The pen and touch pipelines fed from memory, without a tablet or uinput.
+
+use std::io;
+use std::sync::Arc;
+
+use evdevil::event::{Abs, AbsEvent, InputEvent, Key, KeyEvent, KeyState, Syn, SynEvent};
+
+use rm_pad::device::RM2;
+use rm_pad::input::{forward_pen, forward_touch, Collector, EventSource};
+use rm_pad::palm::{self, PalmMode, SharedPalmState};
+use rm_pad::state::{RuntimeState, SharedState};
+use rm_pad::transform::{AffineTransform, CoordinateTransform};
+use rm_pad::{Config, Orientation};
+
+fn abs(abs: Abs, value: i32) -> InputEvent {
+    AbsEvent::new(abs, value).into()
+}
+
+fn key(key: Key, pressed: bool) -> InputEvent {
+    KeyEvent::new(key, if pressed { KeyState::PRESSED } else { KeyState::RELEASED }).into()
+}
+
+fn syn() -> InputEvent {
+    SynEvent::new(Syn::REPORT).into()
+}
+
+/// Encode events as the reMarkable 2's 16-byte `input_event`s.
+fn encode(events: &[InputEvent]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(events.len() * RM2.input_event_size);
+    for event in events {
+        bytes.extend([0; 8]);
+        bytes.extend(event.event_type().raw().to_le_bytes());
+        bytes.extend(event.raw_code().to_le_bytes());
+        bytes.extend(event.raw_value().to_le_bytes());
+    }
+    bytes
+}
+
+fn values(frame: &[InputEvent]) -> Vec<(u16, u16, i32)> {
+    frame.iter().map(|e| (e.event_type().raw(), e.raw_code(), e.raw_value())).collect()
+}
+
+fn value(event: InputEvent) -> (u16, u16, i32) {
+    values(&[event])[0]
+}
+
+fn state(config: &Config, palm: &Option<SharedPalmState>) -> SharedState {
+    Arc::new(RuntimeState::new(config, &RM2, palm.clone()))
+}
+
+fn run_pen(events: &[InputEvent], config: &Config, state: &SharedState, palm: Option<SharedPalmState>) -> Collector {
+    let mut sink = Collector::default();
+    let input = encode(events);
+    let result = forward_pen(&mut input.as_slice(), &mut sink, config, &RM2, state, palm, state.grab());
+    assert!(result.unwrap_err().is_end_of_stream());
+    sink
+}
+
+fn run_touch(events: &[InputEvent], config: &Config, state: &SharedState, palm: Option<SharedPalmState>) -> Collector {
+    let mut sink = Collector::default();
+    let input = encode(events);
+    let result = forward_touch(&mut input.as_slice(), &mut sink, config, &RM2, state, palm, state.grab());
+    assert!(result.unwrap_err().is_end_of_stream());
+    sink
+}
+
+#[test]
+fn test_pen_batching() {
+    let config = Config::new(&RM2);
+    let state = state(&config, &None);
+    let events = [
+        key(Key::BTN_TOOL_PEN, true),
+        abs(Abs::X, 1000),
+        abs(Abs::Y, 2000),
+        abs(Abs::PRESSURE, 300),
+        syn(),
+        abs(Abs::PRESSURE, 0),
+        syn(),
+    ];
+    let sink = run_pen(&events, &config, &state, None);
+
+    let transform = CoordinateTransform::new(None, config.orientation, RM2.pen_x_max, RM2.pen_y_max);
+    let (x, y) = transform.transform_pen(1000, 2000, RM2.pen_x_max, RM2.pen_y_max);
+    assert_eq!(sink.frames.len(), 3);
+    // BTN_TOUCH derived from the pressure first, then the position
+    assert_eq!(
+        values(&sink.frames[0]),
+        values(&[
+            key(Key::BTN_TOUCH, true),
+            abs(Abs::X, x),
+            abs(Abs::Y, y),
+            key(Key::BTN_TOOL_PEN, true),
+            abs(Abs::PRESSURE, 300),
+            syn(),
+        ])
+    );
+    assert_eq!(
+        values(&sink.frames[1]),
+        values(&[key(Key::BTN_TOUCH, false), abs(Abs::PRESSURE, 0), syn()])
+    );
+    // The end of the stream takes the pen out of range
+    assert!(values(&sink.frames[2]).contains(&value(key(Key::BTN_TOOL_PEN, false))));
+    assert_eq!(state.status().pen.frames, 2);
+}
+
+#[test]
+fn test_touch_slots() {
+    let config = Config::new(&RM2);
+    let state = state(&config, &None);
+    let events = [
+        abs(Abs::MT_SLOT, 0),
+        abs(Abs::MT_TRACKING_ID, 40),
+        abs(Abs::MT_POSITION_X, 100),
+        abs(Abs::MT_POSITION_Y, 200),
+        abs(Abs::MT_SLOT, 1),
+        abs(Abs::MT_TRACKING_ID, 41),
+        abs(Abs::MT_POSITION_X, 300),
+        abs(Abs::MT_POSITION_Y, 400),
+        syn(),
+        abs(Abs::MT_SLOT, 0),
+        abs(Abs::MT_TRACKING_ID, -1),
+        syn(),
+    ];
+    let sink = run_touch(&events, &config, &state, None);
+    assert_eq!(sink.frames.len(), 3);
+
+    // Both contacts start with tracking IDs of their own
+    let first = values(&sink.frames[0]);
+    let slot_0 = first.iter().position(|&e| e == value(abs(Abs::MT_SLOT, 0))).unwrap();
+    let slot_1 = first.iter().position(|&e| e == value(abs(Abs::MT_SLOT, 1))).unwrap();
+    assert_eq!(first[slot_0 + 1], value(abs(Abs::MT_TRACKING_ID, 1)));
+    assert_eq!(first[slot_1 + 1], value(abs(Abs::MT_TRACKING_ID, 2)));
+    assert!(first.contains(&value(key(Key::BTN_TOOL_DOUBLETAP, true))));
+
+    // Lifting slot 0 ends its contact and keeps slot 1 going
+    let second = values(&sink.frames[1]);
+    assert_eq!(&second[..2], [value(abs(Abs::MT_SLOT, 0)), value(abs(Abs::MT_TRACKING_ID, -1))]);
+    assert!(!second.contains(&value(abs(Abs::MT_TRACKING_ID, 2))));
+    assert!(second.contains(&value(key(Key::BTN_TOOL_FINGER, true))));
+
+    // The end of the stream lifts the remaining contact
+    let last = values(&sink.frames[2]);
+    assert_eq!(&last[..2], [value(abs(Abs::MT_SLOT, 1)), value(abs(Abs::MT_TRACKING_ID, -1))]);
+    assert_eq!(state.status().touch.frames, 2);
+}
+
+#[test]
+fn test_global_palm_rejection() {
+    let mut config = Config::new(&RM2);
+    config.palm_mode = PalmMode::Global;
+    let palm = palm::create_shared_state(&config);
+    let state = state(&config, &palm);
+
+    // The pen was just lifted, so touches stay suppressed for the grace period
+    let pen = [key(Key::BTN_TOOL_PEN, true), abs(Abs::X, 1000), abs(Abs::Y, 2000), abs(Abs::PRESSURE, 300), syn()];
+    run_pen(&pen, &config, &state, palm.clone());
+
+    let touch = [
+        abs(Abs::MT_SLOT, 0),
+        abs(Abs::MT_TRACKING_ID, 7),
+        abs(Abs::MT_POSITION_X, 100),
+        abs(Abs::MT_POSITION_Y, 200),
+        syn(),
+    ];
+    let sink = run_touch(&touch, &config, &state, palm);
+
+    let positions = sink.frames.iter().flatten().filter(|e| e.raw_code() == Abs::MT_POSITION_X.raw());
+    assert_eq!(positions.count(), 0);
+    assert_eq!(state.status().touch.palm_suppressed, 1);
+}
+
+#[test]
+fn test_smart_palm_rejection() {
+    let mut config = Config::new(&RM2);
+    config.palm_mode = PalmMode::Smart;
+    config.palm_touch_major_max = Some(50);
+    let palm = palm::create_shared_state(&config);
+    let state = state(&config, &palm);
+
+    // A fingertip and a contact too large to be one
+    let events = [
+        abs(Abs::MT_SLOT, 0),
+        abs(Abs::MT_TRACKING_ID, 7),
+        abs(Abs::MT_POSITION_X, 100),
+        abs(Abs::MT_POSITION_Y, 200),
+        abs(Abs::MT_TOUCH_MAJOR, 10),
+        abs(Abs::MT_SLOT, 1),
+        abs(Abs::MT_TRACKING_ID, 8),
+        abs(Abs::MT_POSITION_X, 900),
+        abs(Abs::MT_POSITION_Y, 1200),
+        abs(Abs::MT_TOUCH_MAJOR, 120),
+        syn(),
+    ];
+    let sink = run_touch(&events, &config, &state, palm);
+
+    let first = values(&sink.frames[0]);
+    assert!(first.contains(&value(abs(Abs::MT_SLOT, 0))));
+    assert!(!first.contains(&value(abs(Abs::MT_SLOT, 1))));
+    assert!(first.contains(&value(key(Key::BTN_TOOL_FINGER, true))));
+}
+
+/// Reads events from memory and lifts the pen once `lift_at` bytes are read.
+struct LiftPen<'a> {
+    input: &'a [u8],
+    read: usize,
+    lift_at: usize,
+    palm: SharedPalmState,
+}
+
+impl EventSource for LiftPen<'_> {
+    fn read_event(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if self.read == self.lift_at {
+            self.palm.lock().unwrap().pen_down = false;
+        }
+        self.input.read_event(buf)?;
+        self.read += buf.len();
+        Ok(())
+    }
+}
+
+#[test]
+fn test_exclusion_zone_during_palm_suppression() {
+    let mut config = Config::new(&RM2);
+    config.palm_mode = PalmMode::Global;
+    config.palm_grace_ms = 0;
+    config.touch_exclusion.left_mm = 10.0;
+    config.touch_exclusion.right_mm = 10.0;
+    config.touch_exclusion.top_mm = 10.0;
+    config.touch_exclusion.bottom_mm = 10.0;
+    let palm = palm::create_shared_state(&config).unwrap();
+    palm.lock().unwrap().pen_down = true;
+    let state = state(&config, &Some(palm.clone()));
+
+    // A contact in a corner and one in the middle land while the pen is down
+    let suppressed = [
+        abs(Abs::MT_SLOT, 0),
+        abs(Abs::MT_TRACKING_ID, 7),
+        abs(Abs::MT_POSITION_X, 10),
+        abs(Abs::MT_POSITION_Y, 10),
+        abs(Abs::MT_SLOT, 1),
+        abs(Abs::MT_TRACKING_ID, 8),
+        abs(Abs::MT_POSITION_X, 700),
+        abs(Abs::MT_POSITION_Y, 900),
+        syn(),
+    ];
+    let moved = [abs(Abs::MT_SLOT, 0), abs(Abs::MT_POSITION_X, 12), abs(Abs::MT_SLOT, 1), abs(Abs::MT_POSITION_X, 702), syn()];
+    let input = encode(&[&suppressed[..], &moved[..]].concat());
+    let lift_at = encode(&suppressed).len();
+    let mut source = LiftPen { input: &input, read: 0, lift_at, palm: palm.clone() };
+    let mut sink = Collector::default();
+    let result = forward_touch(&mut source, &mut sink, &config, &RM2, &state, Some(palm), state.grab());
+    assert!(result.unwrap_err().is_end_of_stream());
+
+    // Once the pen lifts, only the contact in the middle is forwarded
+    let moved = values(&sink.frames[1]);
+    assert!(moved.contains(&value(abs(Abs::MT_SLOT, 1))));
+    assert!(!moved.contains(&value(abs(Abs::MT_SLOT, 0))));
+    let tracking_ids: Vec<_> = sink
+        .frames
+        .iter()
+        .flat_map(|frame| values(frame))
+        .filter(|&(_, code, _)| code == Abs::MT_TRACKING_ID.raw())
+        .collect();
+    assert_eq!(tracking_ids, values(&[abs(Abs::MT_TRACKING_ID, 1), abs(Abs::MT_TRACKING_ID, -1)]));
+    assert_eq!(state.status().touch.palm_suppressed, 1);
+}
+
+/// Reads events from memory and runs `then` before reporting the end of the
+/// stream, while the pen is still where the events left it.
+struct Then<'a, F: FnMut()> {
+    input: &'a [u8],
+    then: F,
+}
+
+impl<F: FnMut()> EventSource for Then<'_, F> {
+    fn read_event(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        if self.input.is_empty() {
+            (self.then)();
+        }
+        self.input.read_event(buf)
+    }
+}
+
+#[test]
+fn test_palm_hover_distance() {
+    let mut config = Config::new(&RM2);
+    config.palm_mode = PalmMode::Global;
+    config.palm_hover_distance = Some(0.5);
+    let touch = [
+        abs(Abs::MT_SLOT, 0),
+        abs(Abs::MT_TRACKING_ID, 7),
+        abs(Abs::MT_POSITION_X, 100),
+        abs(Abs::MT_POSITION_Y, 200),
+        syn(),
+    ];
+
+    // Touches are suppressed only while the pen hovers below the threshold
+    for (distance, suppressed) in [(200, 0), (50, 1)] {
+        let palm = palm::create_shared_state(&config);
+        let state = state(&config, &palm);
+        let pen = [key(Key::BTN_TOOL_PEN, true), abs(Abs::X, 1000), abs(Abs::Y, 2000), abs(Abs::DISTANCE, distance), syn()];
+        let pen = encode(&pen);
+        let mut source = Then { input: &pen, then: || drop(run_touch(&touch, &config, &state, palm.clone())) };
+        let mut sink = Collector::default();
+        let result = forward_pen(&mut source, &mut sink, &config, &RM2, &state, palm.clone(), state.grab());
+        assert!(result.unwrap_err().is_end_of_stream());
+        assert_eq!(state.status().touch.palm_suppressed, suppressed);
+    }
+}
+
+#[test]
+fn test_smart_palm_rejection_with_pen_transform() {
+    let mut config = Config::new(&RM2);
+    config.palm_mode = PalmMode::Smart;
+    config.orientation = Orientation::Portrait;
+    // Mirrored pen output, so pen and touch output spaces disagree
+    config.pen_transform = Some(AffineTransform::from([[-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]));
+    let palm = palm::create_shared_state(&config);
+    let state = state(&config, &palm);
+
+    // In portrait, the pen writes at 30% across and half way down, with a
+    // contact just below and right of it and one in the far corner
+    let pen = [
+        key(Key::BTN_TOOL_PEN, true),
+        abs(Abs::X, 10484),
+        abs(Abs::Y, 4717),
+        abs(Abs::PRESSURE, 300),
+        syn(),
+    ];
+    let touch = [
+        abs(Abs::MT_SLOT, 0),
+        abs(Abs::MT_TRACKING_ID, 7),
+        abs(Abs::MT_POSITION_X, 449),
+        abs(Abs::MT_POSITION_Y, 842),
+        abs(Abs::MT_SLOT, 1),
+        abs(Abs::MT_TRACKING_ID, 8),
+        abs(Abs::MT_POSITION_X, 1263),
+        abs(Abs::MT_POSITION_Y, 1684),
+        syn(),
+    ];
+    let mut touch_sink = None;
+    let pen = encode(&pen);
+    let run = || touch_sink = Some(run_touch(&touch, &config, &state, palm.clone()));
+    let mut source = Then { input: &pen, then: run };
+    let mut sink = Collector::default();
+    let result = forward_pen(&mut source, &mut sink, &config, &RM2, &state, palm.clone(), state.grab());
+    assert!(result.unwrap_err().is_end_of_stream());
+
+    let first = values(&touch_sink.unwrap().frames[0]);
+    assert!(!first.contains(&value(abs(Abs::MT_SLOT, 0))));
+    assert!(first.contains(&value(abs(Abs::MT_SLOT, 1))));
+}